@@ -0,0 +1,217 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use eyre::{Report, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::webhook::{Attachment, Method, WebhookExecutor, WebhookRequest};
+
+/// A request that could not be delivered, along with where it was headed and why it failed
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// Whether the request was to create or edit a message
+    #[serde(default)]
+    pub method: Method,
+    /// Webhook or message URL the request was sent to
+    pub url: String,
+    /// The request itself
+    pub request: WebhookRequest,
+    /// Files that were to be uploaded with the request
+    #[serde(default)]
+    pub files: Vec<Attachment>,
+    /// RFC 3339 timestamp of the final failed attempt
+    pub failed_at: String,
+    /// Why the final attempt failed
+    pub error: String,
+}
+
+/// A directory of [DeadLetter]s, one JSON file each
+#[derive(Debug, Clone)]
+pub struct DeadLetterStore {
+    dir: PathBuf,
+}
+
+impl DeadLetterStore {
+    /// The directory is created when the first request is stored, not here
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Save a failed request, returning the path it was written to
+    pub fn store(
+        &self,
+        method: Method,
+        url: &str,
+        request: &WebhookRequest,
+        files: &[Attachment],
+        error: &Report,
+    ) -> Result<PathBuf> {
+        fs::create_dir_all(&self.dir)?;
+
+        let now = Utc::now();
+        let letter = DeadLetter {
+            method,
+            url: url.to_string(),
+            request: request.clone(),
+            files: files.to_vec(),
+            failed_at: now.to_rfc3339(),
+            error: format!("{error:#}"),
+        };
+
+        // Timestamped names keep replay in failure order, the random suffix avoids collisions within a timestamp
+        let path = self.dir.join(format!(
+            "{}-{:08x}.json",
+            now.format("%Y%m%dT%H%M%S%.6f"),
+            rand::random::<u32>()
+        ));
+        let f = fs::OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&path)?;
+        serde_json::to_writer_pretty(f, &letter)?;
+
+        Ok(path)
+    }
+
+    /// Attempt to deliver every stored request again, oldest first. Delivered requests are removed from the store,
+    /// ones that fail again are left in place. Returns the number of delivered and failed requests.
+    pub async fn replay(&self, client: WebhookExecutor) -> Result<(usize, usize)> {
+        let mut paths = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .map(|e| e.map(|e| e.path()))
+                .collect::<Result<Vec<_>, _>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        paths.retain(|p| p.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+
+        let (mut delivered, mut failed) = (0, 0);
+        for path in paths {
+            match replay_one(&path, client.clone()).await {
+                Ok(()) => {
+                    info!("Delivered {}", path.display());
+                    fs::remove_file(&path)?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    warn!("Failed to replay {}: {e}", path.display());
+                    failed += 1;
+                }
+            }
+        }
+
+        Ok((delivered, failed))
+    }
+}
+
+/// Load and deliver a single dead letter, without storing it again if it fails
+async fn replay_one(path: &Path, client: WebhookExecutor) -> Result<()> {
+    let letter: DeadLetter = serde_json::from_slice(&fs::read(path)?)?;
+
+    letter
+        .request
+        .deliver(client, letter.method, &letter.url, &letter.files)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::mock::MockDiscord;
+    use crate::retry::RetryPolicy;
+    use crate::webhook::Embed;
+
+    fn request() -> WebhookRequest {
+        WebhookRequest {
+            content: Some("hello".into()),
+            embeds: vec![Embed::builder().title("New movie added").build().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    fn thumb() -> Attachment {
+        Attachment {
+            filename: "thumb.jpg".into(),
+            content_type: "image/jpeg".into(),
+            data: vec![0xFF, 0xD8, 0xFF],
+        }
+    }
+
+    fn stored(store: &DeadLetterStore) -> Vec<PathBuf> {
+        match fs::read_dir(&store.dir) {
+            Ok(entries) => entries.map(|e| e.unwrap().path()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_stored_requests_and_removes_them() {
+        let discord = MockDiscord::start();
+        let dir = tempfile::tempdir().unwrap();
+        let store = DeadLetterStore::new(dir.path().join("dead"));
+
+        let url = discord.url("/api/webhooks/1/abc");
+        let path = store
+            .store(
+                Method::Post,
+                &url,
+                &request(),
+                &[thumb()],
+                &eyre::eyre!("boom"),
+            )
+            .unwrap();
+
+        let letter: DeadLetter = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(letter.url, url);
+        assert_eq!(letter.error, "boom");
+        assert_eq!(letter.files[0].data, thumb().data);
+
+        let (delivered, failed) = store.replay(WebhookExecutor::new()).await.unwrap();
+        assert_eq!((delivered, failed), (1, 0));
+        assert!(stored(&store).is_empty());
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].uri, "/api/webhooks/1/abc");
+        assert!(requests[0]
+            .content_type
+            .as_deref()
+            .unwrap()
+            .starts_with("multipart/form-data"));
+        assert!(requests[0].body_str().contains(r#""content":"hello""#));
+    }
+
+    #[tokio::test]
+    async fn keeps_requests_that_fail_again() {
+        let discord = MockDiscord::start();
+        discord.reply(400, Vec::new(), r#"{"message":"Invalid Form Body"}"#);
+        let dir = tempfile::tempdir().unwrap();
+        let store = DeadLetterStore::new(dir.path());
+
+        let url = discord.url("/api/webhooks/1/abc/messages/5");
+        store
+            .store(Method::Patch, &url, &request(), &[], &eyre::eyre!("boom"))
+            .unwrap();
+
+        let client = WebhookExecutor::new().with_retry_policy(RetryPolicy {
+            max_attempts: 1,
+            ..Default::default()
+        });
+        assert_eq!(store.replay(client).await.unwrap(), (0, 1));
+        assert_eq!(stored(&store).len(), 1);
+        assert_eq!(discord.requests()[0].method, "PATCH");
+    }
+
+    #[tokio::test]
+    async fn replaying_an_empty_store_does_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DeadLetterStore::new(dir.path().join("missing"));
+        assert_eq!(store.replay(WebhookExecutor::new()).await.unwrap(), (0, 0));
+    }
+}
@@ -0,0 +1,42 @@
+//! Provides facilities to construct basic rich messages and an API for posting those to a discord webhook
+//!
+//! ```no_run
+//! use discord_webhook::webhook::{Embed, WebhookExecutor, WebhookRequest};
+//!
+//! # async fn send() -> eyre::Result<()> {
+//! // Clones share a connection pool and rate limit state, so make one and pass it around
+//! let client = WebhookExecutor::new();
+//!
+//! let embed = Embed::builder()
+//!     .title("Hello from a webhook")
+//!     .color(0xE5A00D)
+//!     .build()?;
+//! let request = WebhookRequest {
+//!     embeds: vec![embed],
+//!     ..Default::default()
+//! };
+//!
+//! request
+//!     .execute(client, "https://discord.com/api/webhooks/...", &[])
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+#![warn(missing_docs)]
+
+/// Contains a webhook executor function and related state structures
+pub mod webhook;
+
+/// Tracks discord's rate limit buckets so requests can be delayed rather than rejected
+mod ratelimit;
+
+/// Policy for retrying deliveries that failed for transient reasons
+pub mod retry;
+
+/// On-disk store for requests that could not be delivered, so they may be replayed later
+pub mod dead_letter;
+
+/// A stand-in for discord's webhook API, to test against
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
@@ -0,0 +1,132 @@
+//! A stand-in for discord's webhook API, recording every request it gets. By default it replies to requests that wait
+//! for their message (and to edits) with a new message, and to anything else with no content, like discord does.
+//! Other replies can be queued up ahead of time.
+
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+
+/// A request the mock received
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    /// HTTP method, like `POST`
+    pub method: String,
+    /// Path and query
+    pub uri: String,
+    /// Content type of the body, if it had one
+    pub content_type: Option<String>,
+    /// The body, as it was sent
+    pub body: Bytes,
+}
+
+impl Recorded {
+    /// The body as text, for checking what was sent
+    pub fn body_str(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// A queued reply: status, headers and body
+type Reply = (u16, Vec<(&'static str, String)>, String);
+
+#[derive(Debug, Default)]
+struct State {
+    requests: Vec<Recorded>,
+    replies: VecDeque<Reply>,
+    next_id: u64,
+}
+
+/// Handle to a running mock, cheap to clone
+#[derive(Debug, Clone)]
+pub struct MockDiscord {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockDiscord {
+    /// Start a mock on a free local port, running until the test's runtime shuts down
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        Self { addr, state }
+    }
+
+    /// URL of a webhook on the mock
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// Reply to the next request that comes in with this, instead of the default
+    pub fn reply(&self, status: u16, headers: Vec<(&'static str, String)>, body: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .replies
+            .push_back((status, headers, body.to_string()));
+    }
+
+    /// Every request received so far, in order
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let method = req.method().to_string();
+    let uri = req.uri().to_string();
+    let content_type = req
+        .headers()
+        .get("content-type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .unwrap_or_default();
+
+    let mut state = state.lock().unwrap();
+    let waits = uri.contains("wait=true") || method == "PATCH";
+    state.requests.push(Recorded {
+        method,
+        uri,
+        content_type,
+        body,
+    });
+
+    let (status, headers, body) = match state.replies.pop_front() {
+        Some(reply) => reply,
+        None if waits => {
+            state.next_id += 1;
+            let id = state.next_id;
+            (
+                200,
+                vec![("content-type", "application/json".to_string())],
+                format!(r#"{{"id":"{id}","channel_id":"c{id}"}}"#),
+            )
+        }
+        None => (204, Vec::new(), String::new()),
+    };
+
+    let mut resp = Response::builder().status(StatusCode::from_u16(status).unwrap());
+    for (name, value) in headers {
+        resp = resp.header(name, value);
+    }
+    Ok(resp.body(Body::from(body)).unwrap())
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+
+use hyper::HeaderMap;
+use tokio::time::{Duration, Instant};
+
+/// State of a single rate limit bucket, as last reported by discord
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// Requests left in this bucket until it resets
+    remaining: u32,
+    /// When the bucket refills
+    reset_at: Instant,
+}
+
+/// Tracks the rate limit buckets discord reports in response headers, so requests can be delayed instead of rejected
+///
+/// Discord does not document which routes share a bucket, so each webhook URL is mapped to the bucket ID that was
+/// reported the last time it was used. Unknown routes are allowed through until a response says otherwise.
+#[derive(Debug, Default)]
+pub(crate) struct RateLimiter {
+    /// Maps a webhook route (URL without query string) to the bucket ID discord reported for it
+    routes: HashMap<String, String>,
+    /// Bucket states by bucket ID
+    buckets: HashMap<String, Bucket>,
+    /// Set when discord reports a global rate limit, all requests wait until this passes
+    global_reset: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Reserve a request against the bucket of this route. If the bucket is exhausted, returns how long to wait
+    /// before trying again; otherwise the request is counted against the bucket and [None] is returned.
+    pub(crate) fn acquire(&mut self, route: &str) -> Option<Duration> {
+        let now = Instant::now();
+
+        if let Some(reset) = self.global_reset {
+            if reset > now {
+                return Some(reset - now);
+            }
+            self.global_reset = None;
+        }
+
+        let bucket = self
+            .routes
+            .get(route)
+            .and_then(|id| self.buckets.get_mut(id))?;
+
+        if bucket.reset_at <= now {
+            // Bucket has refilled, but we don't know to what. Let this one through and learn from the reply
+            return None;
+        }
+
+        if bucket.remaining == 0 {
+            Some(bucket.reset_at - now)
+        } else {
+            bucket.remaining -= 1;
+            None
+        }
+    }
+
+    /// Update bucket state from the X-RateLimit-* headers of a response to a request on this route
+    pub(crate) fn update(&mut self, route: &str, headers: &HeaderMap) {
+        let bucket_id = match header_str(headers, "x-ratelimit-bucket") {
+            Some(id) => id.to_string(),
+            None => return,
+        };
+
+        let remaining = header_str(headers, "x-ratelimit-remaining").and_then(|s| s.parse().ok());
+        let reset_after = header_str(headers, "x-ratelimit-reset-after").and_then(parse_seconds);
+
+        if let (Some(remaining), Some(reset_after)) = (remaining, reset_after) {
+            self.buckets.insert(
+                bucket_id.clone(),
+                Bucket {
+                    remaining,
+                    reset_at: Instant::now() + reset_after,
+                },
+            );
+        }
+
+        self.routes.insert(route.to_string(), bucket_id);
+    }
+
+    /// Record a 429 reply. Blocks the whole bucket for this route (or everything, if the limit is global) for
+    /// `retry_after`
+    pub(crate) fn limited(&mut self, route: &str, retry_after: Duration, global: bool) {
+        let reset_at = Instant::now() + retry_after;
+
+        if global {
+            self.global_reset = Some(reset_at);
+        } else if let Some(id) = self.routes.get(route) {
+            self.buckets.insert(
+                id.clone(),
+                Bucket {
+                    remaining: 0,
+                    reset_at,
+                },
+            );
+        } else {
+            // No bucket known yet, key a placeholder bucket on the route itself
+            self.routes.insert(route.to_string(), route.to_string());
+            self.buckets.insert(
+                route.to_string(),
+                Bucket {
+                    remaining: 0,
+                    reset_at,
+                },
+            );
+        }
+    }
+}
+
+/// Fetch a header as a string, if present and valid
+pub(crate) fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Discord reports times as fractional seconds, parse those into a [Duration]
+pub(crate) fn parse_seconds(s: &str) -> Option<Duration> {
+    s.trim().parse::<f64>().ok().and_then(seconds)
+}
+
+/// Convert fractional seconds into a [Duration], if sensible
+pub(crate) fn seconds(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hyper::header::HeaderValue;
+
+    const ROUTE: &str = "https://discord.com/api/webhooks/1/abc";
+
+    fn headers(bucket: &str, remaining: u32, reset_after: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-bucket", HeaderValue::from_str(bucket).unwrap());
+        headers.insert("x-ratelimit-remaining", remaining.into());
+        headers.insert(
+            "x-ratelimit-reset-after",
+            HeaderValue::from_str(reset_after).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unknown_routes_go_through() {
+        let mut limiter = RateLimiter::default();
+        assert_eq!(limiter.acquire(ROUTE), None);
+        assert_eq!(limiter.acquire(ROUTE), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_an_exhausted_bucket_to_reset() {
+        let mut limiter = RateLimiter::default();
+        limiter.update(ROUTE, &headers("b1", 1, "2.5"));
+
+        assert_eq!(limiter.acquire(ROUTE), None);
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_millis(2500)));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_millis(1500)));
+
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert_eq!(limiter.acquire(ROUTE), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn routes_share_the_bucket_discord_reports() {
+        let other = "https://discord.com/api/webhooks/2/def";
+        let mut limiter = RateLimiter::default();
+        limiter.update(ROUTE, &headers("shared", 0, "1"));
+        limiter.update(other, &headers("shared", 0, "1"));
+
+        assert_eq!(limiter.acquire(other), Some(Duration::from_secs(1)));
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_secs(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn incomplete_headers_are_ignored() {
+        let mut limiter = RateLimiter::default();
+        let mut partial = headers("b1", 0, "1");
+        partial.remove("x-ratelimit-reset-after");
+        limiter.update(ROUTE, &partial);
+        limiter.update(ROUTE, &HeaderMap::new());
+
+        assert_eq!(limiter.acquire(ROUTE), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn too_many_requests_blocks_the_route() {
+        let other = "https://discord.com/api/webhooks/2/def";
+        let mut limiter = RateLimiter::default();
+
+        // Before any headers were seen the route gets a bucket of its own
+        limiter.limited(ROUTE, Duration::from_secs(3), false);
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_secs(3)));
+        assert_eq!(limiter.acquire(other), None);
+
+        // Afterwards the reported bucket is blocked, which has room again once the wait is over
+        limiter.update(ROUTE, &headers("b1", 5, "10"));
+        limiter.limited(ROUTE, Duration::from_secs(2), false);
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_secs(2)));
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(limiter.acquire(ROUTE), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limits_block_every_route() {
+        let other = "https://discord.com/api/webhooks/2/def";
+        let mut limiter = RateLimiter::default();
+        limiter.limited(ROUTE, Duration::from_secs(4), true);
+
+        assert_eq!(limiter.acquire(ROUTE), Some(Duration::from_secs(4)));
+        assert_eq!(limiter.acquire(other), Some(Duration::from_secs(4)));
+
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert_eq!(limiter.acquire(ROUTE), None);
+        assert_eq!(limiter.acquire(other), None);
+    }
+
+    #[test]
+    fn parses_fractional_seconds() {
+        assert_eq!(parse_seconds("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_seconds(" 2 "), Some(Duration::from_secs(2)));
+        assert_eq!(parse_seconds("-1"), None);
+        assert_eq!(parse_seconds("soon"), None);
+    }
+}
@@ -0,0 +1,108 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Controls how many times, and how far apart, failed deliveries are retried
+///
+/// Only transient failures (network errors and 5xx replies) are retried, a 4xx reply will not get better by asking
+/// again. Rate limits are handled separately and do not count as attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts to make, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_backoff: Duration,
+    /// Fraction (0 to 1) of each delay that is randomized, so that many failed requests don't all retry at once
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(60),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// How long to wait after the given (1-based) failed attempt before trying again
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter > 0.0 {
+            // Shave a random portion of the jittered fraction off the delay
+            delay.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(5),
+            jitter,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = policy(0.0);
+        let delays: Vec<Duration> = (1..=6).map(|attempt| policy.backoff(attempt)).collect();
+
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 4000, 5000, 5000].map(Duration::from_millis)
+        );
+    }
+
+    #[test]
+    fn backoff_survives_huge_attempt_counts() {
+        let policy = policy(0.0);
+        assert_eq!(policy.backoff(0), Duration::from_millis(500));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays_by_its_fraction() {
+        let policy = policy(0.2);
+        for attempt in 1..=6 {
+            let full = RetryPolicy {
+                jitter: 0.0,
+                ..policy
+            }
+            .backoff(attempt);
+            for _ in 0..100 {
+                let delay = policy.backoff(attempt);
+                assert!(delay <= full, "{delay:?} is over {full:?}");
+                assert!(
+                    delay >= full.mul_f64(0.8),
+                    "{delay:?} is under 80% of {full:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn jitter_is_clamped() {
+        let policy = policy(7.0);
+        for _ in 0..100 {
+            assert!(policy.backoff(1) <= Duration::from_millis(500));
+        }
+    }
+}
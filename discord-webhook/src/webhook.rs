@@ -0,0 +1,1776 @@
+use std::sync::{Arc, Mutex};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, TimeZone};
+use hyper::{body::to_bytes, client::HttpConnector, Body, Client, Request};
+
+use hyper::http;
+use hyper_tls::HttpsConnector;
+
+use serde::{Deserialize, Serialize};
+
+use eyre::{eyre, Report, Result};
+use tracing::{debug, error, warn};
+
+use super::dead_letter::DeadLetterStore;
+use super::ratelimit::{header_str, parse_seconds, seconds, RateLimiter};
+use super::retry::RetryPolicy;
+
+/// Give up on a request after being told to back off this many times in a row
+const MAX_RATE_LIMITED_ATTEMPTS: u32 = 16;
+
+// Limits discord enforces on messages, in characters. Anything over these is rejected outright
+const CONTENT_LIMIT: usize = 2000;
+/// Longest embed title discord accepts
+pub const TITLE_LIMIT: usize = 256;
+/// Longest embed description discord accepts
+pub const DESCRIPTION_LIMIT: usize = 4096;
+const FIELD_COUNT_LIMIT: usize = 25;
+const FIELD_NAME_LIMIT: usize = 256;
+const FIELD_VALUE_LIMIT: usize = 1024;
+const FOOTER_LIMIT: usize = 2048;
+const AUTHOR_LIMIT: usize = 256;
+const EMBED_COUNT_LIMIT: usize = 10;
+const USERNAME_LIMIT: usize = 80;
+/// Applies to the sum of all text in all embeds of a message
+const EMBED_TOTAL_LIMIT: usize = 6000;
+
+/// Wrapper around an HTTP client, the rate limit state discord has reported to it, and what to do when deliveries fail
+#[derive(Debug, Clone)]
+pub struct WebhookExecutor {
+    /// Only support https transport, as the discord API is HTTPS only
+    client: Client<HttpsConnector<HttpConnector>>,
+    /// Rate limit buckets shared between all clones of this executor
+    limits: Arc<Mutex<RateLimiter>>,
+    /// How transient failures are retried
+    retry: RetryPolicy,
+    /// Where requests that could not be delivered are kept, if anywhere
+    dead_letters: Option<DeadLetterStore>,
+}
+
+impl WebhookExecutor {
+    /// This initializes a new HTTP Client, can be cloned very cheaply for sharing the underlying client's connection pool
+    pub fn new() -> Self {
+        Self {
+            client: Client::builder().build(HttpsConnector::new()),
+            limits: Arc::new(Mutex::new(RateLimiter::default())),
+            retry: RetryPolicy::default(),
+            dead_letters: None,
+        }
+    }
+
+    /// Replace the default retry policy
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Save requests that exhaust their retries to this store, so they can be replayed later
+    pub fn with_dead_letters(mut self, store: DeadLetterStore) -> Self {
+        self.dead_letters = Some(store);
+        self
+    }
+
+    /// Wait until the rate limit bucket for this route has room for another request
+    async fn reserve(&self, route: &str) {
+        loop {
+            // Don't hold the lock across the sleep
+            let wait = self.limits.lock().unwrap().acquire(route);
+
+            match wait {
+                Some(wait) => {
+                    debug!("Waiting {wait:?} for discord rate limit to reset");
+                    tokio::time::sleep(wait).await;
+                }
+                None => return,
+            }
+        }
+    }
+
+    /// Delete a message previously posted through this webhook URL
+    pub async fn delete_message(&self, url: &str, message_id: &str) -> Result<()> {
+        self.send(
+            Method::Delete,
+            &message_url(url, message_id),
+            None,
+            Bytes::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Send a request, retrying transient failures according to the retry policy. Returns the body of discord's reply
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        content_type: Option<&str>,
+        body: Bytes,
+    ) -> Result<Bytes> {
+        let mut attempt = 1;
+        loop {
+            match self.attempt(method, url, content_type, &body).await {
+                Ok(reply) => return Ok(reply),
+                Err(AttemptError::Permanent(e)) => return Err(e),
+                Err(AttemptError::Transient(e)) if attempt >= self.retry.max_attempts => {
+                    return Err(e.wrap_err(format!("Giving up after {attempt} attempts")))
+                }
+                Err(AttemptError::Transient(e)) => {
+                    let delay = self.retry.backoff(attempt);
+                    warn!("Discord delivery attempt {attempt} failed, retrying in {delay:?}: {e}");
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Make a single delivery attempt, waiting out and retrying through any rate limits along the way. Returns the body
+    /// of discord's reply
+    async fn attempt(
+        &self,
+        method: Method,
+        url: &str,
+        content_type: Option<&str>,
+        body: &Bytes,
+    ) -> Result<Bytes, AttemptError> {
+        // Buckets are tracked per webhook, ignoring any query parameters
+        let route = url.split('?').next().unwrap_or(url);
+
+        for _ in 0..MAX_RATE_LIMITED_ATTEMPTS {
+            self.reserve(route).await;
+
+            let mut req = Request::builder()
+                .method(http::Method::from(method))
+                .uri(url);
+            if let Some(content_type) = content_type {
+                req = req.header("Content-Type", content_type);
+            }
+            let req = req
+                .body(Body::from(body.clone()))
+                .map_err(|e| AttemptError::Permanent(e.into()))?;
+
+            debug!("{:?}", req);
+
+            let mut resp = self
+                .client
+                .request(req)
+                .await
+                .map_err(|e| AttemptError::Transient(e.into()))?;
+
+            debug!("Discord webhook reply status: {}", resp.status());
+
+            self.limits.lock().unwrap().update(route, resp.headers());
+
+            let body_bytes = to_bytes(resp.body_mut())
+                .await
+                .map_err(|e| AttemptError::Transient(e.into()))?;
+
+            if resp.status() == http::StatusCode::TOO_MANY_REQUESTS {
+                // Prefer the body's value since it is more precise, fall back to the header
+                let (retry_after, global) =
+                    match serde_json::from_slice::<RateLimitReply>(&body_bytes) {
+                        Ok(reply) => (seconds(reply.retry_after), reply.global),
+                        Err(_) => (None, false),
+                    };
+                let retry_after = retry_after
+                    .or_else(|| header_str(resp.headers(), "retry-after").and_then(parse_seconds))
+                    .unwrap_or_else(|| std::time::Duration::from_secs(1));
+                let global = global || header_str(resp.headers(), "x-ratelimit-global").is_some();
+
+                warn!("Rate limited by discord, retrying in {retry_after:?} (global: {global})");
+                self.limits
+                    .lock()
+                    .unwrap()
+                    .limited(route, retry_after, global);
+                continue;
+            }
+
+            // This is expected to be status 204, no content, unless discord was asked to wait for the message
+            let status = resp.status();
+            if status.is_success() {
+                return Ok(body_bytes);
+            }
+
+            let e = Report::new(StatusError {
+                status: status.as_u16(),
+                body: String::from_utf8_lossy(&body_bytes).into_owned(),
+            });
+
+            // Server side errors may clear up, anything else is a problem with the request itself
+            return Err(if status.is_server_error() {
+                AttemptError::Transient(e)
+            } else {
+                AttemptError::Permanent(e)
+            });
+        }
+
+        Err(AttemptError::Transient(eyre!(
+            "Still rate limited after {} attempts",
+            MAX_RATE_LIMITED_ATTEMPTS
+        )))
+    }
+}
+
+impl Default for WebhookExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Discord replied to a request with an error status. Failed requests return this inside their [Report], so callers
+/// can tell what went wrong:
+///
+/// ```
+/// use discord_webhook::webhook::StatusError;
+///
+/// # fn check(e: eyre::Report) {
+/// if e.downcast_ref::<StatusError>().is_some_and(StatusError::is_not_found) {
+///     // The message or webhook was deleted
+/// }
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct StatusError {
+    /// HTTP status code of the reply
+    pub status: u16,
+    /// Body of the reply, which from discord is JSON with an error code and message
+    pub body: String,
+}
+
+impl StatusError {
+    /// The message, or webhook, the request was for doesn't exist (any more)
+    pub fn is_not_found(&self) -> bool {
+        self.status == 404
+    }
+}
+
+impl std::fmt::Display for StatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = http::StatusCode::from_u16(self.status)
+            .map(|s| s.to_string())
+            .unwrap_or_else(|_| self.status.to_string());
+        write!(f, "Server replied with {status}: {}", self.body)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+/// Why a single delivery attempt failed, used to decide whether it is worth trying again
+enum AttemptError {
+    /// Network errors and server side failures, which may succeed on a later attempt
+    Transient(Report),
+    /// The request was rejected outright, retrying won't help
+    Permanent(Report),
+}
+
+/// Body of a 429 reply from discord
+#[derive(Debug, Deserialize)]
+struct RateLimitReply {
+    /// Seconds to wait before retrying
+    retry_after: f64,
+    #[serde(default)]
+    global: bool,
+}
+
+/// A rich message embed, a card with a title, text, images and fields. Usually made with [Embed::builder]
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct Embed {
+    /// Bold line at the top
+    pub title: Option<String>,
+    /// Type should always be rich for webhooks, and in general
+    #[serde(rename = "type")]
+    kind: EmbedKind,
+    /// Text under the title, which may use markdown
+    pub description: Option<String>,
+    /// Link for the title
+    pub url: Option<String>,
+    /// RFC 3339 time shown next to the footer
+    pub timestamp: Option<String>,
+    /// Accent color, as `0xRRGGBB`
+    pub color: Option<u32>,
+    /// Small text at the bottom
+    pub footer: Option<EmbedFooter>,
+    /// Large image under the description
+    pub image: Option<EmbedMedia>,
+    /// Small image in the top right corner
+    pub thumbnail: Option<EmbedMedia>,
+    /// Video, only ever set by discord itself
+    pub video: Option<EmbedMedia>,
+    /// Site the embed is from, shown above the author
+    pub provider: Option<EmbedProvider>,
+    /// Name shown above the title
+    pub author: Option<EmbedAuthor>,
+    /// Name and value pairs under the description
+    pub fields: Option<Vec<EmbedField>>,
+}
+
+impl Embed {
+    /// Start building an embed
+    ///
+    /// ```
+    /// use discord_webhook::webhook::Embed;
+    ///
+    /// let embed = Embed::builder()
+    ///     .title("New movie added")
+    ///     .description("Some Film (1999)")
+    ///     .field("Rating", "PG-13", true)
+    ///     .color(0xE5A00D)
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(embed.title.as_deref(), Some("New movie added"));
+    ///
+    /// // Limits are checked when the embed is built
+    /// assert!(Embed::builder().title("x".repeat(300)).build().is_err());
+    /// ```
+    pub fn builder() -> EmbedBuilder {
+        EmbedBuilder::default()
+    }
+
+    /// Continue building on this embed, like a template
+    pub fn into_builder(self) -> EmbedBuilder {
+        EmbedBuilder { embed: self }
+    }
+
+    /// Characters in this embed that count towards discord's total limit
+    pub fn length(&self) -> usize {
+        let fields = self.fields.iter().flatten();
+
+        self.title.as_deref().map_or(0, char_count)
+            + self.description.as_deref().map_or(0, char_count)
+            + self.footer.as_ref().map_or(0, |f| char_count(&f.text))
+            + self.author.as_ref().map_or(0, |a| char_count(&a.name))
+            + fields
+                .map(|f| char_count(&f.name) + char_count(&f.value))
+                .sum::<usize>()
+    }
+
+    /// Check this embed against discord's limits
+    pub fn validate(&self) -> Result<()> {
+        check_length("Embed title", self.title.as_deref(), TITLE_LIMIT)?;
+        check_length(
+            "Embed description",
+            self.description.as_deref(),
+            DESCRIPTION_LIMIT,
+        )?;
+        check_length(
+            "Embed footer",
+            self.footer.as_ref().map(|f| f.text.as_str()),
+            FOOTER_LIMIT,
+        )?;
+        check_length(
+            "Embed author",
+            self.author.as_ref().map(|a| a.name.as_str()),
+            AUTHOR_LIMIT,
+        )?;
+
+        let fields = self.fields.as_deref().unwrap_or_default();
+        if fields.len() > FIELD_COUNT_LIMIT {
+            return Err(eyre!(
+                "Embed has {} fields, at most {} are allowed",
+                fields.len(),
+                FIELD_COUNT_LIMIT
+            ));
+        }
+        for field in fields {
+            check_length("Embed field name", Some(&field.name), FIELD_NAME_LIMIT)?;
+            check_length("Embed field value", Some(&field.value), FIELD_VALUE_LIMIT)?;
+        }
+
+        if self.length() > EMBED_TOTAL_LIMIT {
+            return Err(eyre!(
+                "Embed has {} characters, at most {} are allowed",
+                self.length(),
+                EMBED_TOTAL_LIMIT
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Break this embed up into as many embeds as it takes to fit within discord's limits. Short text like titles and
+    /// field values is truncated, while the description and fields overflow into continuation embeds that only carry
+    /// the overflowing content and this embed's color.
+    ///
+    /// ```
+    /// use discord_webhook::webhook::Embed;
+    ///
+    /// let mut embed = Embed::default();
+    /// embed.title = Some("A long read".into());
+    /// embed.description = Some("word ".repeat(2000));
+    /// assert!(embed.validate().is_err());
+    ///
+    /// let embeds = embed.split();
+    /// assert_eq!(embeds.len(), 3);
+    /// assert!(embeds.iter().all(|e| e.validate().is_ok()));
+    /// ```
+    pub fn split(mut self) -> Vec<Embed> {
+        // Truncate everything that can't reasonably be continued
+        self.title = self.title.map(|t| truncate(t, TITLE_LIMIT));
+        if let Some(footer) = self.footer.as_mut() {
+            footer.text = truncate(std::mem::take(&mut footer.text), FOOTER_LIMIT);
+        }
+        if let Some(author) = self.author.as_mut() {
+            author.name = truncate(std::mem::take(&mut author.name), AUTHOR_LIMIT);
+        }
+        let mut fields: Vec<EmbedField> = self
+            .fields
+            .take()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|f| EmbedField {
+                name: truncate(f.name, FIELD_NAME_LIMIT),
+                value: truncate(f.value, FIELD_VALUE_LIMIT),
+                inline: f.inline,
+            })
+            .collect();
+        let description = self.description.take();
+
+        let continuation = Embed {
+            color: self.color,
+            ..Default::default()
+        };
+        let mut embeds = vec![self];
+
+        // Fill the description into the first embed, with what's left over in continuations
+        let mut rest = description.as_deref().unwrap_or_default();
+        while !rest.is_empty() {
+            let current = embeds.last_mut().unwrap();
+            let budget = DESCRIPTION_LIMIT.min(EMBED_TOTAL_LIMIT.saturating_sub(current.length()));
+            let (chunk, remainder) = take_chunk(rest, budget);
+
+            if chunk.is_empty() {
+                embeds.push(continuation.clone());
+            } else {
+                current.description = Some(chunk.to_string());
+                rest = remainder;
+                if !rest.is_empty() {
+                    embeds.push(continuation.clone());
+                }
+            }
+        }
+
+        // Then add fields to the last embed, starting new ones whenever a limit is hit
+        for field in fields.drain(..) {
+            let current = embeds.last_mut().unwrap();
+            let count = current.fields.as_ref().map_or(0, Vec::len);
+            let field_length = char_count(&field.name) + char_count(&field.value);
+
+            if count >= FIELD_COUNT_LIMIT || current.length() + field_length > EMBED_TOTAL_LIMIT {
+                embeds.push(Embed {
+                    fields: Some(vec![field]),
+                    ..continuation.clone()
+                });
+            } else {
+                current.fields.get_or_insert_with(Vec::new).push(field);
+            }
+        }
+
+        embeds
+    }
+}
+
+/// Builds an [Embed] a piece at a time, checking discord's limits once it is done. See [Embed::builder]
+#[derive(Debug, Default, Clone)]
+pub struct EmbedBuilder {
+    embed: Embed,
+}
+
+impl EmbedBuilder {
+    /// Bold line at the top
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.embed.title = Some(title.into());
+        self
+    }
+
+    /// Text under the title, which may use markdown
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.embed.description = Some(description.into());
+        self
+    }
+
+    /// Link for the title
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.embed.url = Some(url.into());
+        self
+    }
+
+    /// Shown by discord in the reader's local time
+    pub fn timestamp<Tz: TimeZone>(mut self, timestamp: DateTime<Tz>) -> Self
+    where
+        Tz::Offset: std::fmt::Display,
+    {
+        self.embed.timestamp = Some(timestamp.to_rfc3339());
+        self
+    }
+
+    /// Accent color, as `0xRRGGBB`
+    pub fn color(mut self, color: u32) -> Self {
+        self.embed.color = Some(color);
+        self
+    }
+
+    /// Small text at the bottom
+    pub fn footer(mut self, footer: EmbedFooter) -> Self {
+        self.embed.footer = Some(footer);
+        self
+    }
+
+    /// Name shown above the title
+    pub fn author(mut self, author: EmbedAuthor) -> Self {
+        self.embed.author = Some(author);
+        self
+    }
+
+    /// Large image under the description. May be an `attachment://` URL referring to an [Attachment]
+    pub fn image(mut self, url: impl Into<String>) -> Self {
+        self.embed.image = Some(EmbedMedia::new(url));
+        self
+    }
+
+    /// Small image in the top right corner. May be an `attachment://` URL referring to an [Attachment]
+    pub fn thumbnail(mut self, url: impl Into<String>) -> Self {
+        self.embed.thumbnail = Some(EmbedMedia::new(url));
+        self
+    }
+
+    /// Site the embed is from, shown above the author
+    pub fn provider(mut self, name: impl Into<String>, url: Option<String>) -> Self {
+        self.embed.provider = Some(EmbedProvider::new(name, url));
+        self
+    }
+
+    /// Add a field after any already added. Inline fields are laid out side by side where there is room
+    pub fn field(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+        inline: bool,
+    ) -> Self {
+        self.embed
+            .fields
+            .get_or_insert_with(Vec::new)
+            .push(EmbedField::new(name, value, inline));
+        self
+    }
+
+    /// Finish the embed, failing if it is over any of discord's limits
+    pub fn build(self) -> Result<Embed> {
+        self.embed.validate()?;
+        Ok(self.embed)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+enum EmbedKind {
+    #[default]
+    #[serde(rename = "rich")]
+    Rich,
+}
+
+/// Small text at the bottom of an [Embed], with an optional icon
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbedFooter {
+    /// The text itself
+    pub text: String,
+    /// HTTPS link to an icon image
+    pub icon_url: Option<String>,
+    /// Proxied URL to the icon (not sure what this is for)
+    pub proxy_icon_url: Option<String>,
+}
+
+impl EmbedFooter {
+    /// Footer with this text and no icon
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            icon_url: None,
+            proxy_icon_url: None,
+        }
+    }
+
+    /// Show this icon next to the footer text
+    pub fn icon(mut self, url: impl Into<String>) -> Self {
+        self.icon_url = Some(url.into());
+        self
+    }
+}
+
+/// An image or video in an [Embed]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbedMedia {
+    url: String,
+
+    proxy_url: Option<String>,
+    height: Option<u32>,
+    width: Option<u32>,
+}
+
+impl EmbedMedia {
+    /// Media hosted at this URL, which may be an `attachment://` URL referring to an [Attachment]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            proxy_url: None,
+            height: None,
+            width: None,
+        }
+    }
+}
+
+/// The site an [Embed] is from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbedProvider {
+    name: String,
+    url: Option<String>,
+}
+
+impl EmbedProvider {
+    /// Site with this name, linking to the URL if there is one
+    pub fn new(name: impl Into<String>, url: Option<String>) -> Self {
+        Self {
+            name: name.into(),
+            url,
+        }
+    }
+}
+
+/// Name shown above the title of an [Embed], with an optional link and icon
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbedAuthor {
+    /// The name itself
+    pub name: String,
+    /// Link for the name
+    pub url: Option<String>,
+    /// HTTPS link to an icon image
+    pub icon_url: Option<String>,
+    /// Proxied URL to the icon, set by discord
+    pub proxy_icon_url: Option<String>,
+}
+
+impl EmbedAuthor {
+    /// Author with this name and no link or icon
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            url: None,
+            icon_url: None,
+            proxy_icon_url: None,
+        }
+    }
+
+    /// Link for the author's name
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Show this icon next to the author's name
+    pub fn icon(mut self, url: impl Into<String>) -> Self {
+        self.icon_url = Some(url.into());
+        self
+    }
+}
+
+/// A name and value pair in an [Embed]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmbedField {
+    name: String,
+    value: String,
+    inline: Option<bool>,
+}
+
+impl EmbedField {
+    /// Field with this name and value. Inline fields are laid out side by side where there is room
+    pub fn new(name: impl Into<String>, value: impl Into<String>, inline: bool) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            inline: Some(inline),
+        }
+    }
+
+    /// Name of the field, shown in bold
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Value of the field, shown under its name
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+/// Kinds of mentions discord may parse out of message content
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AllowedMentionType {
+    /// Role mentions, like `<@&1234>`
+    Roles,
+    /// User mentions, like `<@1234>`
+    Users,
+    /// `@everyone` and `@here`
+    Everyone,
+}
+
+/// Controls which mentions in the content actually notify anyone. The default allows none of them
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AllowedMention {
+    /// Kinds of mentions to allow wherever they appear
+    #[serde(default)]
+    pub parse: Vec<AllowedMentionType>,
+    /// Role IDs that may be mentioned, without also parsing all role mentions
+    #[serde(default)]
+    pub roles: Vec<String>,
+    /// User IDs that may be mentioned, without also parsing all user mentions
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Whether to ping the author of the message replied to. Webhooks can't reply, so this does nothing
+    #[serde(default)]
+    pub replied_user: bool,
+}
+
+impl AllowedMention {
+    /// Allow exactly these roles and users to be pinged, and nothing else
+    ///
+    /// ```
+    /// use discord_webhook::webhook::AllowedMention;
+    ///
+    /// let allowed = AllowedMention::only(vec!["1234".into()], vec![]);
+    /// assert!(allowed.parse.is_empty());
+    /// assert_eq!(allowed.mentions(), "<@&1234>");
+    /// ```
+    pub fn only(roles: Vec<String>, users: Vec<String>) -> Self {
+        Self {
+            roles,
+            users,
+            ..Default::default()
+        }
+    }
+
+    /// Content that mentions every role and user explicitly allowed here, separated by spaces
+    pub fn mentions(&self) -> String {
+        let roles = self.roles.iter().map(|id| format!("<@&{id}>"));
+        let users = self.users.iter().map(|id| format!("<@{id}>"));
+        roles.chain(users).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// Optional flags that can be sent alongside the main request. Anything left as [None] uses the webhook's defaults
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RequestMetadata {
+    /// Overrides the webhook's name for this message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    /// Overrides the webhook's avatar for this message
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub avatar_url: Option<String>,
+    /// Read the message out loud to those who have the channel open
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tts: Option<bool>,
+    /// Always sent, so that by default nothing in the content (like an `@everyone` in a title) pings anyone
+    #[serde(default)]
+    pub allowed_mentions: AllowedMention,
+    /// When posting to a forum channel, create a new post with this name. See [thread_url] to post in an existing one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_name: Option<String>,
+}
+
+/// The part of a message discord replies with that the relay cares about. Discord only replies with the message if
+/// the webhook URL has `wait=true` in its query, see [wait_url]
+#[derive(Debug, Deserialize, Clone)]
+pub struct Message {
+    /// ID of the message, to edit or delete it by
+    pub id: String,
+    /// Channel the message was posted in. For a new forum post, this is the ID of the post's thread
+    pub channel_id: String,
+}
+
+/// Add a query parameter to a webhook URL, which may already have some
+fn with_query(url: &str, key: &str, value: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}{key}={value}")
+}
+
+/// Webhook URL that posts into an existing thread, or forum post, of the webhook's channel
+///
+/// ```
+/// use discord_webhook::webhook::thread_url;
+///
+/// assert_eq!(
+///     thread_url("https://discord.com/api/webhooks/1/abc", "42"),
+///     "https://discord.com/api/webhooks/1/abc?thread_id=42"
+/// );
+/// ```
+pub fn thread_url(url: &str, thread_id: &str) -> String {
+    with_query(url, "thread_id", thread_id)
+}
+
+/// ID of the webhook with this URL, which looks like `https://discord.com/api/webhooks/<id>/<token>`. Unlike the URL,
+/// the ID is no secret, so it's what to keep when remembering things about a webhook. URLs that don't look like that
+/// give their second to last path segment, leaving out what would be the token
+///
+/// ```
+/// use discord_webhook::webhook::webhook_id;
+///
+/// assert_eq!(webhook_id("https://discord.com/api/webhooks/1234/abc?thread_id=42"), "1234");
+/// ```
+pub fn webhook_id(url: &str) -> &str {
+    let path = url.split('?').next().unwrap_or(url).trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some((rest, _token)) => rest.rsplit('/').next().unwrap_or(rest),
+        None => path,
+    }
+}
+
+/// Webhook URL that makes discord reply with the created [Message]
+pub fn wait_url(url: &str) -> String {
+    with_query(url, "wait", "true")
+}
+
+/// URL of a message previously posted through a webhook, keeping any query (like a `thread_id`) of the webhook URL
+///
+/// ```
+/// use discord_webhook::webhook::message_url;
+///
+/// assert_eq!(
+///     message_url("https://discord.com/api/webhooks/1/abc?thread_id=42", "7"),
+///     "https://discord.com/api/webhooks/1/abc/messages/7?thread_id=42"
+/// );
+/// ```
+pub fn message_url(url: &str, message_id: &str) -> String {
+    match url.split_once('?') {
+        Some((base, query)) => format!("{base}/messages/{message_id}?{query}"),
+        None => format!("{url}/messages/{message_id}"),
+    }
+}
+
+/// How a request is sent. Messages are created with a POST, edited with a PATCH and deleted with a DELETE
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    /// Create a message
+    #[default]
+    Post,
+    /// Edit a message
+    Patch,
+    /// Delete a message
+    Delete,
+}
+
+impl From<Method> for http::Method {
+    fn from(method: Method) -> Self {
+        match method {
+            Method::Post => http::Method::POST,
+            Method::Patch => http::Method::PATCH,
+            Method::Delete => http::Method::DELETE,
+        }
+    }
+}
+
+/// A file uploaded alongside a request. Embeds in the same request may refer to it by [Attachment::url]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    /// Name of the file, which embeds refer to it by
+    pub filename: String,
+    /// MIME type of the file, like `image/jpeg`
+    pub content_type: String,
+    /// Contents of the file
+    pub data: Vec<u8>,
+}
+
+impl Attachment {
+    /// URL to use in embed media fields to refer to this attachment
+    pub fn url(&self) -> String {
+        format!("attachment://{}", self.filename)
+    }
+}
+
+/// A single webhook message, with plain text content, rich embeds or both, and any [RequestMetadata]
+///
+/// ```
+/// use discord_webhook::webhook::{Embed, RequestMetadata, WebhookRequest};
+///
+/// let request = WebhookRequest {
+///     content: Some("Something new is on the server".into()),
+///     embeds: vec![Embed::builder().title("New movie added").build().unwrap()],
+///     metadata: RequestMetadata {
+///         username: Some("Plex".into()),
+///         ..Default::default()
+///     },
+/// };
+/// assert!(request.validate().is_ok());
+/// ```
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct WebhookRequest {
+    /// Plain text of the message, which may use markdown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    /// Embeds shown under the content, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embeds: Vec<Embed>,
+    /// How the message is posted
+    #[serde(flatten)]
+    pub metadata: RequestMetadata,
+}
+
+impl WebhookRequest {
+    /// Check this request against discord's limits, so a request that would be rejected isn't sent at all
+    pub fn validate(&self) -> Result<()> {
+        check_length("Message content", self.content.as_deref(), CONTENT_LIMIT)?;
+        check_length(
+            "Message username",
+            self.metadata.username.as_deref(),
+            USERNAME_LIMIT,
+        )?;
+
+        if self.embeds.len() > EMBED_COUNT_LIMIT {
+            return Err(eyre!(
+                "Message has {} embeds, at most {} are allowed",
+                self.embeds.len(),
+                EMBED_COUNT_LIMIT
+            ));
+        }
+
+        for embed in &self.embeds {
+            embed.validate()?;
+        }
+
+        let total: usize = self.embeds.iter().map(Embed::length).sum();
+        if total > EMBED_TOTAL_LIMIT {
+            return Err(eyre!(
+                "Message embeds have {} characters in total, at most {} are allowed",
+                total,
+                EMBED_TOTAL_LIMIT
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Split this request into as many requests as it takes to fit within discord's limits, in order. Content comes
+    /// first, and shares a request with the first embeds if it fits in one. See [Embed::split] for how oversized
+    /// embeds are handled. Every request keeps this request's metadata.
+    ///
+    /// ```
+    /// use discord_webhook::webhook::WebhookRequest;
+    ///
+    /// let request = WebhookRequest {
+    ///     content: Some("line\n".repeat(1000)),
+    ///     ..Default::default()
+    /// };
+    /// assert!(request.validate().is_err());
+    ///
+    /// let requests = request.split();
+    /// assert_eq!(requests.len(), 3);
+    /// assert!(requests.iter().all(|r| r.validate().is_ok()));
+    /// ```
+    pub fn split(self) -> Vec<WebhookRequest> {
+        let piece = |content: Option<String>, embeds: Vec<Embed>| WebhookRequest {
+            content,
+            embeds,
+            metadata: self.metadata.clone(),
+        };
+
+        let mut requests = Vec::new();
+        let mut rest = self.content.as_deref().unwrap_or_default();
+        while !rest.is_empty() {
+            let (chunk, remainder) = take_chunk(rest, CONTENT_LIMIT);
+            requests.push(piece(Some(chunk.to_string()), Vec::new()));
+            rest = remainder;
+        }
+
+        let mut groups = Vec::new();
+        let mut current: Vec<Embed> = Vec::new();
+        let mut current_length = 0;
+
+        for embed in self.embeds.iter().cloned().flat_map(Embed::split) {
+            let length = embed.length();
+
+            if !current.is_empty()
+                && (current.len() >= EMBED_COUNT_LIMIT
+                    || current_length + length > EMBED_TOTAL_LIMIT)
+            {
+                groups.push(std::mem::take(&mut current));
+                current_length = 0;
+            }
+
+            current_length += length;
+            current.push(embed);
+        }
+        if !current.is_empty() {
+            groups.push(current);
+        }
+
+        let mut groups = groups.into_iter();
+        // The last piece of content carries the first embeds, so short content stays with its embeds
+        match (requests.last_mut(), groups.next()) {
+            (Some(last), Some(first)) => last.embeds = first,
+            (None, Some(first)) => requests.push(piece(None, first)),
+            _ => {}
+        }
+        requests.extend(groups.map(|embeds| piece(None, embeds)));
+
+        // Nothing to split, but send what there is (probably just files)
+        if requests.is_empty() {
+            requests.push(self);
+        }
+        requests
+    }
+
+    /// Post this request to a webhook URL. Rate limits reported by discord are honored by delaying the request, and
+    /// transient failures are retried according to the executor's [RetryPolicy]. If the request still can't be
+    /// delivered it is saved to the executor's dead letter store, if it has one, unless discord says the webhook is
+    /// gone.
+    ///
+    /// Any files are uploaded alongside the request as a multipart form. If the URL asks discord to wait (see
+    /// [wait_url]), the created message is returned.
+    pub async fn execute(
+        &self,
+        client: WebhookExecutor,
+        url: &str,
+        files: &[Attachment],
+    ) -> Result<Option<Message>> {
+        self.dispatch(client, Method::Post, url, files).await
+    }
+
+    /// Replace the content of a message previously posted through this webhook URL with this request, the same way
+    /// [WebhookRequest::execute] posts one. Any files replace those attached to the message, without files the
+    /// attached ones are kept. Edits of messages that have been deleted fail with a [StatusError] that
+    /// [is_not_found](StatusError::is_not_found), and aren't saved as dead letters.
+    pub async fn edit(
+        &self,
+        client: WebhookExecutor,
+        url: &str,
+        message_id: &str,
+        files: &[Attachment],
+    ) -> Result<Option<Message>> {
+        self.dispatch(client, Method::Patch, &message_url(url, message_id), files)
+            .await
+    }
+
+    async fn dispatch(
+        &self,
+        client: WebhookExecutor,
+        method: Method,
+        url: &str,
+        files: &[Attachment],
+    ) -> Result<Option<Message>> {
+        let result = self.deliver(client.clone(), method, url, files).await;
+
+        // Once the message or webhook is gone, no replay will bring it back
+        let gone = |e: &Report| {
+            e.downcast_ref::<StatusError>()
+                .is_some_and(StatusError::is_not_found)
+        };
+        if let (Err(e), Some(store)) = (&result, &client.dead_letters) {
+            if gone(e) {
+                return result;
+            }
+            match store.store(method, url, self, files, e) {
+                Ok(path) => warn!("Saved undeliverable request to {}", path.display()),
+                Err(store_err) => error!("Failed to save undeliverable request: {store_err}"),
+            }
+        }
+
+        result
+    }
+
+    /// Send this request to a URL with retries, but without falling back to the dead letter store
+    pub async fn deliver(
+        &self,
+        client: WebhookExecutor,
+        method: Method,
+        url: &str,
+        files: &[Attachment],
+    ) -> Result<Option<Message>> {
+        // Discord would reject this anyway, no sense retrying it
+        self.validate()?;
+
+        let (content_type, body) = self.encode(method, files)?;
+        let reply = client.send(method, url, Some(&content_type), body).await?;
+
+        // Without waiting discord replies with no content at all
+        if reply.is_empty() {
+            Ok(None)
+        } else {
+            Ok(serde_json::from_slice(&reply).ok())
+        }
+    }
+
+    /// Serialize this request into a request body and its content type. Without files this is plain JSON, with files
+    /// it is a multipart form with the JSON in a `payload_json` part and each file in a `files[n]` part
+    fn encode(&self, method: Method, files: &[Attachment]) -> Result<(String, Bytes)> {
+        if files.is_empty() {
+            let json = serde_json::to_vec(self)?;
+            return Ok(("application/json".to_string(), Bytes::from(json)));
+        }
+
+        let mut json = serde_json::to_value(self)?;
+        if method == Method::Patch {
+            // Edits keep only the attachments listed, so list just the new files to replace the old ones
+            let attachments: Vec<_> = files
+                .iter()
+                .enumerate()
+                .map(|(n, file)| serde_json::json!({ "id": n, "filename": file.filename }))
+                .collect();
+            json["attachments"] = attachments.into();
+        }
+        let json = serde_json::to_vec(&json)?;
+
+        let boundary = format!("{:032x}", rand::random::<u128>());
+        let mut body = BytesMut::new();
+
+        body.put(format!("--{boundary}\r\n").as_bytes());
+        body.put(&b"Content-Disposition: form-data; name=\"payload_json\"\r\n"[..]);
+        body.put(&b"Content-Type: application/json\r\n\r\n"[..]);
+        body.put(&json[..]);
+        body.put(&b"\r\n"[..]);
+
+        for (n, file) in files.iter().enumerate() {
+            // Quotes would end the filename early, and discord doesn't need them anyway
+            let filename = file.filename.replace('"', "");
+            body.put(format!("--{boundary}\r\n").as_bytes());
+            body.put(
+                format!(
+                    "Content-Disposition: form-data; name=\"files[{n}]\"; filename=\"{filename}\"\r\n"
+                )
+                .as_bytes(),
+            );
+            body.put(format!("Content-Type: {}\r\n\r\n", file.content_type).as_bytes());
+            body.put(&file.data[..]);
+            body.put(&b"\r\n"[..]);
+        }
+        body.put(format!("--{boundary}--\r\n").as_bytes());
+
+        Ok((
+            format!("multipart/form-data; boundary={boundary}"),
+            body.freeze(),
+        ))
+    }
+}
+
+fn char_count(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn check_length(what: &str, value: Option<&str>, limit: usize) -> Result<()> {
+    match value.map(char_count) {
+        Some(length) if length > limit => Err(eyre!(
+            "{} is {} characters long, at most {} are allowed",
+            what,
+            length,
+            limit
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Cut a string down to `limit` characters, marking it with an ellipsis if anything was removed
+///
+/// ```
+/// use discord_webhook::webhook::truncate;
+///
+/// assert_eq!(truncate("Heat".into(), 10), "Heat");
+/// assert_eq!(truncate("The Shawshank Redemption".into(), 10), "The Shaws…");
+/// ```
+pub fn truncate(s: String, limit: usize) -> String {
+    if char_count(&s) <= limit {
+        return s;
+    }
+
+    let mut truncated: String = s.chars().take(limit.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Take up to `limit` characters off the front of some text, preferring to break after a newline. Returns the chunk
+/// taken and what remains.
+fn take_chunk(text: &str, limit: usize) -> (&str, &str) {
+    let end = match text.char_indices().nth(limit) {
+        Some((end, _)) => end,
+        None => return (text, ""),
+    };
+
+    let end = match text[..end].rfind('\n') {
+        Some(newline) if newline > 0 => newline + 1,
+        _ => end,
+    };
+
+    text.split_at(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use crate::mock::MockDiscord;
+
+    fn embed_with_fields(count: usize, value: &str) -> Embed {
+        Embed {
+            fields: Some(
+                (0..count)
+                    .map(|n| EmbedField::new(format!("f{n}"), value, true))
+                    .collect(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn title_limit_is_inclusive() {
+        let mut embed = Embed {
+            title: Some("t".repeat(TITLE_LIMIT)),
+            ..Default::default()
+        };
+        assert!(embed.validate().is_ok());
+
+        embed.title = Some("t".repeat(TITLE_LIMIT + 1));
+        assert!(embed.validate().is_err());
+
+        let split = embed.split();
+        assert_eq!(split.len(), 1);
+        let title = split[0].title.as_deref().unwrap();
+        assert_eq!(char_count(title), TITLE_LIMIT);
+        assert!(title.ends_with('…'));
+    }
+
+    #[test]
+    fn limits_count_characters_not_bytes() {
+        let mut embed = Embed {
+            title: Some("é".repeat(TITLE_LIMIT)),
+            description: Some("日".repeat(DESCRIPTION_LIMIT)),
+            ..Default::default()
+        };
+        assert!(embed.validate().is_ok());
+        assert_eq!(embed.clone().split().len(), 1);
+
+        embed.description = Some("日".repeat(DESCRIPTION_LIMIT + 1));
+        assert!(embed.validate().is_err());
+    }
+
+    #[test]
+    fn description_overflows_into_continuations() {
+        let embed = Embed {
+            title: Some("Title".into()),
+            color: Some(0x123456),
+            description: Some("日".repeat(DESCRIPTION_LIMIT + 10)),
+            ..Default::default()
+        };
+
+        let split = embed.split();
+        assert_eq!(split.len(), 2);
+        assert_eq!(
+            char_count(split[0].description.as_deref().unwrap()),
+            DESCRIPTION_LIMIT
+        );
+        assert_eq!(split[1].description.as_deref(), Some(&*"日".repeat(10)));
+
+        // Continuations only carry the overflow and the color
+        assert_eq!(split[1].title, None);
+        assert_eq!(split[1].color, Some(0x123456));
+        assert!(split.iter().all(|e| e.validate().is_ok()));
+    }
+
+    #[test]
+    fn too_many_fields_overflow_into_continuations() {
+        let embed = embed_with_fields(FIELD_COUNT_LIMIT + 1, "v");
+        assert!(embed.validate().is_err());
+
+        let split = embed.split();
+        assert_eq!(split.len(), 2);
+        assert_eq!(split[0].fields.as_ref().unwrap().len(), FIELD_COUNT_LIMIT);
+        assert_eq!(split[1].fields.as_ref().unwrap().len(), 1);
+        assert_eq!(split[1].fields.as_ref().unwrap()[0].name, "f25");
+    }
+
+    #[test]
+    fn field_text_is_truncated() {
+        let embed = Embed {
+            fields: Some(vec![EmbedField::new(
+                "n".repeat(FIELD_NAME_LIMIT + 1),
+                "ü".repeat(FIELD_VALUE_LIMIT + 1),
+                false,
+            )]),
+            ..Default::default()
+        };
+
+        let split = embed.split();
+        let field = &split[0].fields.as_ref().unwrap()[0];
+        assert_eq!(char_count(&field.name), FIELD_NAME_LIMIT);
+        assert_eq!(char_count(&field.value), FIELD_VALUE_LIMIT);
+        assert!(split[0].validate().is_ok());
+    }
+
+    #[test]
+    fn total_limit_splits_embeds() {
+        // 10 fields of 1000 characters are within every other limit, but not the total
+        let embed = embed_with_fields(10, &"x".repeat(1000));
+        assert!(embed.validate().is_err());
+
+        let split = embed.split();
+        assert!(split.len() > 1);
+        assert!(split.iter().all(|e| e.validate().is_ok()));
+        assert_eq!(
+            split
+                .iter()
+                .map(|e| e.fields.as_ref().map_or(0, Vec::len))
+                .sum::<usize>(),
+            10
+        );
+    }
+
+    #[test]
+    fn too_many_embeds_split_into_messages() {
+        let request = WebhookRequest {
+            embeds: vec![Embed::default(); EMBED_COUNT_LIMIT + 1],
+            ..Default::default()
+        };
+        assert!(request.validate().is_err());
+
+        let pieces = request.split();
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].embeds.len(), EMBED_COUNT_LIMIT);
+        assert_eq!(pieces[1].embeds.len(), 1);
+    }
+
+    #[test]
+    fn embeds_over_the_total_split_into_messages() {
+        let embed = Embed {
+            description: Some("x".repeat(4000)),
+            ..Default::default()
+        };
+        let request = WebhookRequest {
+            embeds: vec![embed.clone(), embed],
+            ..Default::default()
+        };
+        assert!(request.validate().is_err());
+
+        let pieces = request.split();
+        assert_eq!(pieces.len(), 2);
+        assert!(pieces.iter().all(|r| r.validate().is_ok()));
+    }
+
+    #[test]
+    fn chunks_prefer_breaking_after_newlines() {
+        assert_eq!(take_chunk("ab\ncd\nef", 7), ("ab\ncd\n", "ef"));
+        assert_eq!(take_chunk("abcdef", 4), ("abcd", "ef"));
+        assert_eq!(take_chunk("short", 10), ("short", ""));
+        // A newline right at the start would make no progress
+        assert_eq!(take_chunk("\nabcdef", 4), ("\nabc", "def"));
+        // Multi-byte characters are never cut in half
+        assert_eq!(take_chunk("日本語テキスト", 3), ("日本語", "テキスト"));
+    }
+
+    #[test]
+    fn truncate_marks_what_was_cut() {
+        assert_eq!(truncate("abc".into(), 3), "abc");
+        assert_eq!(truncate("abcd".into(), 3), "ab…");
+        assert_eq!(truncate("日本語テキスト".into(), 4), "日本語…");
+        assert_eq!(truncate("abc".into(), 0), "…");
+    }
+
+    fn attachment(filename: &str, data: &[u8]) -> Attachment {
+        Attachment {
+            filename: filename.into(),
+            content_type: "image/jpeg".into(),
+            data: data.to_vec(),
+        }
+    }
+
+    fn titled(title: &str) -> WebhookRequest {
+        WebhookRequest {
+            embeds: vec![Embed::builder().title(title).build().unwrap()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn encodes_json_without_files() {
+        let (content_type, body) = titled("Heat").encode(Method::Post, &[]).unwrap();
+        assert_eq!(content_type, "application/json");
+
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["embeds"][0]["title"], "Heat");
+        // Sent even when empty, so nothing pings anyone by default
+        assert_eq!(json["allowed_mentions"]["parse"], serde_json::json!([]));
+        assert!(json.get("content").is_none());
+    }
+
+    #[test]
+    fn encodes_a_multipart_form_with_files() {
+        let data = [0xFF, 0xD8, b'\r', b'\n', b'-', b'-', 0x00];
+        let files = [
+            attachment("poster.jpg", &data),
+            attachment("say \"cheese\".jpg", b"second"),
+        ];
+        let (content_type, body) = titled("Heat").encode(Method::Post, &files).unwrap();
+
+        let boundary = content_type
+            .strip_prefix("multipart/form-data; boundary=")
+            .unwrap();
+        let delimiter = format!("--{boundary}");
+        let body = body.to_vec();
+
+        // Split the body on the delimiter by hand, the file data is binary so it can't be treated as a string
+        let mut parts = Vec::new();
+        let mut rest = &body[..];
+        while let Some(at) = rest
+            .windows(delimiter.len())
+            .position(|w| w == delimiter.as_bytes())
+        {
+            parts.push(&rest[..at]);
+            rest = &rest[at + delimiter.len()..];
+        }
+        assert_eq!(parts.remove(0), b"");
+        assert_eq!(rest, b"--\r\n");
+        assert_eq!(parts.len(), 3);
+
+        let part = |n: usize| {
+            let text = parts[n];
+            let split = text.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+            (
+                String::from_utf8(text[..split].to_vec()).unwrap(),
+                &text[split + 4..text.len() - 2],
+            )
+        };
+
+        let (headers, json) = part(0);
+        assert!(headers.contains(r#"name="payload_json""#), "{headers}");
+        assert!(
+            headers.contains("Content-Type: application/json"),
+            "{headers}"
+        );
+        let json: serde_json::Value = serde_json::from_slice(json).unwrap();
+        assert_eq!(json["embeds"][0]["title"], "Heat");
+
+        let (headers, value) = part(1);
+        assert!(
+            headers.contains(r#"name="files[0]"; filename="poster.jpg""#),
+            "{headers}"
+        );
+        assert!(headers.contains("Content-Type: image/jpeg"), "{headers}");
+        assert_eq!(value, data);
+
+        // Quotes in the filename would end it early
+        let (headers, value) = part(2);
+        assert!(
+            headers.contains(r#"name="files[1]"; filename="say cheese.jpg""#),
+            "{headers}"
+        );
+        assert_eq!(value, b"second");
+    }
+
+    #[tokio::test]
+    async fn executes_with_and_without_waiting() {
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let url = discord.url("/api/webhooks/1/abc");
+
+        let message = titled("Heat")
+            .execute(client.clone(), &url, &[])
+            .await
+            .unwrap();
+        assert!(message.is_none());
+
+        let message = titled("Heat")
+            .execute(
+                client,
+                &wait_url(&url),
+                &[attachment("poster.jpg", b"jpeg")],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, "1");
+        assert_eq!(message.channel_id, "c1");
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].uri, "/api/webhooks/1/abc");
+        assert_eq!(
+            requests[0].content_type.as_deref(),
+            Some("application/json")
+        );
+        assert_eq!(requests[1].uri, "/api/webhooks/1/abc?wait=true");
+        assert!(requests[1]
+            .content_type
+            .as_deref()
+            .unwrap()
+            .starts_with("multipart/form-data; boundary="));
+        assert!(requests[1].body_str().contains("poster.jpg"));
+    }
+
+    #[tokio::test]
+    async fn edits_and_deletes_messages_in_threads() {
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let url = thread_url(&discord.url("/api/webhooks/1/abc"), "42");
+
+        let message = titled("Heat")
+            .edit(client.clone(), &url, "7", &[])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, "1");
+        client.delete_message(&url, "7").await.unwrap();
+
+        let requests = discord.requests();
+        assert_eq!(requests[0].method, "PATCH");
+        assert_eq!(
+            requests[0].uri,
+            "/api/webhooks/1/abc/messages/7?thread_id=42"
+        );
+        assert!(requests[0].body_str().contains(r#""title":"Heat""#));
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(
+            requests[1].uri,
+            "/api/webhooks/1/abc/messages/7?thread_id=42"
+        );
+        assert_eq!(requests[1].content_type, None);
+        assert!(requests[1].body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_not_sent() {
+        let discord = MockDiscord::start();
+        let request = WebhookRequest {
+            content: Some("x".repeat(CONTENT_LIMIT + 1)),
+            ..Default::default()
+        };
+
+        let result = request
+            .execute(
+                WebhookExecutor::new(),
+                &discord.url("/api/webhooks/1/abc"),
+                &[],
+            )
+            .await;
+        assert!(result.is_err());
+        assert!(discord.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn error_replies_keep_their_status() {
+        let discord = MockDiscord::start();
+        discord.reply(
+            404,
+            Vec::new(),
+            r#"{"message": "Unknown Message", "code": 10008}"#,
+        );
+        discord.reply(400, Vec::new(), r#"{"message": "Invalid Form Body"}"#);
+        let client = WebhookExecutor::new();
+        let url = discord.url("/api/webhooks/1/abc");
+
+        let e = titled("Heat")
+            .edit(client.clone(), &url, "7", &[])
+            .await
+            .unwrap_err();
+        let status = e.downcast_ref::<StatusError>().unwrap();
+        assert!(status.is_not_found());
+        assert!(status.body.contains("10008"));
+        assert!(
+            e.to_string()
+                .starts_with("Server replied with 404 Not Found"),
+            "{e}"
+        );
+
+        let e = titled("Heat").execute(client, &url, &[]).await.unwrap_err();
+        let status = e.downcast_ref::<StatusError>().unwrap();
+        assert_eq!(status.status, 400);
+        assert!(!status.is_not_found());
+    }
+
+    #[tokio::test]
+    async fn missing_messages_are_not_dead_lettered() {
+        let discord = MockDiscord::start();
+        discord.reply(404, Vec::new(), r#"{"message": "Unknown Message"}"#);
+        discord.reply(400, Vec::new(), r#"{"message": "Invalid Form Body"}"#);
+        let dir = tempfile::tempdir().unwrap();
+        let client = WebhookExecutor::new().with_dead_letters(DeadLetterStore::new(dir.path()));
+        let url = discord.url("/api/webhooks/1/abc");
+        let letters = || std::fs::read_dir(dir.path()).unwrap().count();
+
+        assert!(titled("Heat")
+            .edit(client.clone(), &url, "7", &[])
+            .await
+            .is_err());
+        assert_eq!(letters(), 0);
+
+        // Anything else is kept, in case it can be fixed up and replayed
+        assert!(titled("Heat").edit(client, &url, "7", &[]).await.is_err());
+        assert_eq!(letters(), 1);
+    }
+
+    /// Send to a webhook that is rate limited with this reply. Returns how long the webhook is held up for, and whether
+    /// other webhooks are held up too
+    async fn rate_limited(headers: Vec<(&'static str, String)>, body: &str) -> (Duration, bool) {
+        let discord = MockDiscord::start();
+        discord.reply(429, headers, body);
+        let client = WebhookExecutor::new();
+        let limited = discord.url("/api/webhooks/1/abc");
+        let other = discord.url("/api/webhooks/2/def");
+
+        let first = tokio::spawn({
+            let (client, limited) = (client.clone(), limited.clone());
+            async move { titled("Heat").execute(client, &limited, &[]).await }
+        });
+        let wait = loop {
+            if let Some(wait) = client.limits.lock().unwrap().acquire(&limited) {
+                break wait;
+            }
+            tokio::task::yield_now().await;
+        };
+        let held_up = client.limits.lock().unwrap().acquire(&other).is_some();
+
+        // The limited request still goes through once the wait is over
+        first.await.unwrap().unwrap();
+        assert_eq!(discord.requests().len(), 2);
+        (wait, held_up)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limits_wait_for_the_body_retry_after_over_the_header() {
+        let (wait, _) = rate_limited(
+            vec![("retry-after", "60".into())],
+            r#"{"message": "You are being rate limited.", "retry_after": 2.5, "global": false}"#,
+        )
+        .await;
+        assert_eq!(wait, Duration::from_millis(2500));
+
+        let (wait, _) = rate_limited(vec![("retry-after", "3".into())], "slow down").await;
+        assert_eq!(wait, Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_rate_limits_hold_up_every_webhook() {
+        let (_, held_up) = rate_limited(Vec::new(), r#"{"retry_after": 5, "global": true}"#).await;
+        assert!(held_up);
+        let (_, held_up) = rate_limited(
+            vec![("x-ratelimit-global", "true".into())],
+            r#"{"retry_after": 5}"#,
+        )
+        .await;
+        assert!(held_up);
+
+        // Other webhooks carry on when the limit is only for the one
+        let (_, held_up) = rate_limited(Vec::new(), r#"{"retry_after": 5, "global": false}"#).await;
+        assert!(!held_up);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limited_routes_hold_up_the_next_send() {
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let url = discord.url("/api/webhooks/1/abc");
+        client
+            .limits
+            .lock()
+            .unwrap()
+            .limited(&url, Duration::from_secs(3), false);
+
+        let start = tokio::time::Instant::now();
+        titled("Heat").execute(client, &url, &[]).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(3));
+        assert_eq!(discord.requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn server_errors_are_retried() {
+        let discord = MockDiscord::start();
+        discord.reply(500, Vec::new(), "oops");
+        let url = discord.url("/api/webhooks/1/abc");
+
+        let message = titled("Heat")
+            .execute(WebhookExecutor::new(), &wait_url(&url), &[])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.id, "1");
+        assert_eq!(discord.requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn bad_requests_are_not_retried() {
+        let discord = MockDiscord::start();
+        discord.reply(400, Vec::new(), r#"{"message": "Invalid Form Body"}"#);
+        let url = discord.url("/api/webhooks/1/abc");
+
+        let e = titled("Heat")
+            .execute(WebhookExecutor::new(), &url, &[])
+            .await
+            .unwrap_err();
+        assert_eq!(e.downcast_ref::<StatusError>().unwrap().status, 400);
+        assert_eq!(discord.requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_that_run_out_of_retries_can_be_replayed() {
+        let discord = MockDiscord::start();
+        for _ in 0..3 {
+            discord.reply(500, Vec::new(), "oops");
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let store = DeadLetterStore::new(dir.path());
+        let client = WebhookExecutor::new()
+            .with_retry_policy(RetryPolicy {
+                max_attempts: 3,
+                ..Default::default()
+            })
+            .with_dead_letters(store.clone());
+        let url = discord.url("/api/webhooks/1/abc");
+
+        let e = titled("Heat")
+            .execute(client.clone(), &url, &[attachment("poster.jpg", b"jpeg")])
+            .await
+            .unwrap_err();
+        assert!(
+            e.to_string().starts_with("Giving up after 3 attempts"),
+            "{e}"
+        );
+        assert_eq!(discord.requests().len(), 3);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Once discord is back the same request goes through, poster and all
+        assert_eq!(store.replay(client).await.unwrap(), (1, 0));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].uri, "/api/webhooks/1/abc");
+        assert!(requests[3].body_str().contains(r#""title":"Heat""#));
+        assert!(requests[3].body_str().contains(r#"filename="poster.jpg""#));
+    }
+
+    #[test]
+    fn edits_with_files_replace_the_attachments() {
+        let files = [attachment("thumb.jpg", b"jpeg")];
+
+        let (_, body) = titled("Heat").encode(Method::Patch, &files).unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(
+            body.contains(r#""attachments":[{"filename":"thumb.jpg","id":0}]"#),
+            "{body}"
+        );
+
+        // New messages have nothing to replace
+        let (_, body) = titled("Heat").encode(Method::Post, &files).unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("attachments"));
+        let (_, body) = titled("Heat").encode(Method::Patch, &[]).unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("attachments"));
+    }
+
+    #[test]
+    fn webhooks_are_known_by_id() {
+        let url = "https://discord.com/api/webhooks/1234/s3cr3t";
+        assert_eq!(webhook_id(url), "1234");
+        assert_eq!(webhook_id(&format!("{url}/")), "1234");
+        assert_eq!(webhook_id(&format!("{url}?thread_id=42&wait=true")), "1234");
+        assert_eq!(
+            webhook_id("https://discordapp.com/api/v10/webhooks/99/t"),
+            "99"
+        );
+    }
+}
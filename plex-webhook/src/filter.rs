@@ -0,0 +1,110 @@
+use bytes::{Buf, BufMut};
+use futures::{Stream, TryStreamExt};
+use warp::{Filter, Rejection};
+
+use crate::webhook::{parse, PlexWebhookRequest, WebhookError};
+
+impl warp::reject::Reject for WebhookError {}
+
+/// Filter extracting a [PlexWebhookRequest] from the request body, reading at most `max_length` bytes of it. Requests
+/// that aren't webhooks are rejected with a [WebhookError], which can be recovered to reply with its status.
+///
+/// ```no_run
+/// use warp::Filter;
+///
+/// # async fn serve() {
+/// let api = warp::path("plex")
+///     .and(warp::post())
+///     .and(plex_webhook::filter::webhook(1024 * 1024))
+///     .map(|request: plex_webhook::webhook::PlexWebhookRequest| {
+///         println!("Received {}", request.payload.event);
+///         warp::reply()
+///     });
+///
+/// warp::serve(api).run(([127, 0, 0, 1], 8001)).await;
+/// # }
+/// ```
+pub fn webhook(
+    max_length: u64,
+) -> impl Filter<Extract = (PlexWebhookRequest,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("content-type")
+        .and(warp::body::stream())
+        .and_then(move |content_type: Option<String>, body| async move {
+            let body = read_body(body, max_length).await?;
+            parse(content_type.as_deref().unwrap_or_default(), &body).map_err(warp::reject::custom)
+        })
+}
+
+/// Fold the stream that makes up the body into a vec, stopping as soon as it goes over the limit
+async fn read_body<S, B>(body: S, limit: u64) -> Result<Vec<u8>, Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+{
+    futures::pin_mut!(body);
+
+    let mut value = Vec::new();
+    while let Some(chunk) = body
+        .try_next()
+        .await
+        .map_err(|e| warp::reject::custom(WebhookError::Form(e.to_string())))?
+    {
+        if (value.len() + chunk.remaining()) as u64 > limit {
+            return Err(warp::reject::custom(WebhookError::TooLarge { limit }));
+        }
+        value.put(chunk);
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "--boundary\r\n\
+        Content-Disposition: form-data; name=\"payload\"\r\n\r\n\
+        {\"event\": \"media.play\"}\r\n\
+        --boundary--\r\n";
+
+    #[tokio::test]
+    async fn extracts_the_webhook() {
+        let request = warp::test::request()
+            .method("POST")
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(BODY)
+            .filter(&webhook(BODY.len() as u64))
+            .await
+            .unwrap();
+        assert_eq!(request.payload.event.as_str(), "media.play");
+    }
+
+    #[tokio::test]
+    async fn rejects_bodies_over_the_limit() {
+        let rejection = warp::test::request()
+            .method("POST")
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(BODY)
+            .filter(&webhook(BODY.len() as u64 - 1))
+            .await
+            .unwrap_err();
+
+        let error = rejection.find::<WebhookError>().unwrap();
+        assert!(matches!(error, WebhookError::TooLarge { .. }), "{error:?}");
+        assert_eq!(error.status(), 413);
+        assert_eq!(error.kind(), "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn rejects_requests_without_a_content_type() {
+        let rejection = warp::test::request()
+            .method("POST")
+            .body(BODY)
+            .filter(&webhook(1024))
+            .await
+            .unwrap_err();
+
+        let error = rejection.find::<WebhookError>().unwrap();
+        assert_eq!(error.status(), 415);
+    }
+}
@@ -0,0 +1,17 @@
+//! This crate provides a basic API to translate between the form submitted by a plex server and
+//! types that can be used to interact with the posted data
+//!
+//! [webhook::parse] works with any web framework, given the request's content type and body. With the `warp` feature,
+//! `filter::webhook` does the same as a warp filter.
+
+#![warn(missing_docs)]
+
+/// Provides structures and types to represent the data posted by plex
+pub mod models;
+
+/// Parses the multipart forms plex posts into [webhook::PlexWebhookRequest]s
+pub mod webhook;
+
+/// Provides a filter that can be composed into a warp [warp::Filter]
+#[cfg(feature = "warp")]
+pub mod filter;
@@ -0,0 +1,434 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+// As with metadata, which of these fields are present depends on the event and the server sending it, so only the
+//  fields that are needed to make sense of the struct at all are required
+
+/// The plex account that caused an event
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Account {
+    /// Plex's ID for the account
+    pub id: Option<u64>,
+    /// URL of the account's avatar
+    pub thumb: Option<String>,
+    /// The account's user name
+    pub title: Option<String>,
+}
+
+/// The plex server that sent an event
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Server {
+    /// Name of the server
+    pub title: Option<String>,
+    /// Unique ID of the server
+    pub uuid: Option<String>,
+}
+
+/// The app or device an item is played on
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Player {
+    /// Whether the player is on the server's own network
+    #[serde(default)]
+    pub local: bool,
+    /// Address the player connected from, when it isn't local
+    pub public_address: Option<String>,
+    /// Name of the player, like `Living Room TV`
+    pub title: Option<String>,
+    /// Unique ID of the player
+    pub uuid: Option<String>,
+}
+
+/// The kind of event a webhook was sent for. Event names plex sends that aren't known here are kept in
+/// [Event::Unknown], so new events don't cause the whole payload to be rejected.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum Event {
+    /// An item was added to someone's On Deck
+    LibraryOnDeck,
+    /// An item was added to a library
+    LibraryNew,
+    /// Playback was paused
+    MediaPause,
+    /// Playback started
+    MediaPlay,
+    /// An item was rated
+    MediaRate,
+    /// Playback was resumed
+    MediaResume,
+    /// An item was played far enough to count as watched
+    MediaScrobble,
+    /// Playback stopped
+    MediaStop,
+    /// The server backed up its database
+    AdminDatabaseBackup,
+    /// The server found its database to be corrupted
+    AdminDatabaseCorrupted,
+    /// A device the server hasn't seen before connected to it
+    DeviceNew,
+    /// Playback started on a shared server, sent to the server's owner
+    PlaybackStarted,
+    /// Any other event, by the name plex sent
+    Unknown(String),
+}
+
+impl Event {
+    /// Every event plex is known to send, in the order they're declared
+    pub const KNOWN: &'static [Event] = &[
+        Event::LibraryOnDeck,
+        Event::LibraryNew,
+        Event::MediaPause,
+        Event::MediaPlay,
+        Event::MediaRate,
+        Event::MediaResume,
+        Event::MediaScrobble,
+        Event::MediaStop,
+        Event::AdminDatabaseBackup,
+        Event::AdminDatabaseCorrupted,
+        Event::DeviceNew,
+        Event::PlaybackStarted,
+    ];
+
+    /// The event name as plex sends it, like `library.new`
+    pub fn as_str(&self) -> &str {
+        match self {
+            Event::LibraryOnDeck => "library.on.deck",
+            Event::LibraryNew => "library.new",
+            Event::MediaPause => "media.pause",
+            Event::MediaPlay => "media.play",
+            Event::MediaRate => "media.rate",
+            Event::MediaResume => "media.resume",
+            Event::MediaScrobble => "media.scrobble",
+            Event::MediaStop => "media.stop",
+            Event::AdminDatabaseBackup => "admin.database.backup",
+            Event::AdminDatabaseCorrupted => "admin.database.corrupted",
+            Event::DeviceNew => "device.new",
+            Event::PlaybackStarted => "playback.started",
+            Event::Unknown(name) => name,
+        }
+    }
+}
+
+impl From<String> for Event {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "library.on.deck" => Event::LibraryOnDeck,
+            "library.new" => Event::LibraryNew,
+            "media.pause" => Event::MediaPause,
+            "media.play" => Event::MediaPlay,
+            "media.rate" => Event::MediaRate,
+            "media.resume" => Event::MediaResume,
+            "media.scrobble" => Event::MediaScrobble,
+            "media.stop" => Event::MediaStop,
+            "admin.database.backup" => Event::AdminDatabaseBackup,
+            "admin.database.corrupted" => Event::AdminDatabaseCorrupted,
+            "device.new" => Event::DeviceNew,
+            "playback.started" => Event::PlaybackStarted,
+            _ => Event::Unknown(name),
+        }
+    }
+}
+
+impl From<Event> for String {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::Unknown(name) => name,
+            known => known.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Someone credited on an item, like a director or a member of the cast
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Credit {
+    /// Library filter for everything else they're credited on, like `director=30`
+    pub filter: Option<String>,
+    /// Plex's ID for the person
+    pub id: Option<u32>,
+    /// The person's name
+    pub tag: String,
+    /// The character played, for the cast
+    pub role: Option<String>,
+    /// URL of a photo of the person
+    pub thumb: Option<String>,
+}
+
+/// An entry in an item's list of IDs in external databases
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Link {
+    /// The ID, like `imdb://tt0111161`
+    pub id: ExternalId,
+}
+
+/// An ID for an item, either plex's own or one from an external database, parsed from strings like `imdb://tt0111161`.
+/// Kinds of IDs that aren't known here are kept whole in [ExternalId::Unknown].
+///
+/// ```
+/// use plex_webhook::models::ExternalId;
+///
+/// let id = ExternalId::from("imdb://tt0111161".to_string());
+/// assert_eq!(id, ExternalId::Imdb("tt0111161".into()));
+/// assert_eq!(id.url("movie").as_deref(), Some("https://www.imdb.com/title/tt0111161/"));
+///
+/// let id = ExternalId::from("tmdb://1396".to_string());
+/// assert_eq!(id.url("show").as_deref(), Some("https://www.themoviedb.org/tv/1396"));
+///
+/// let id = ExternalId::from("com.plexapp.agents.none://123".to_string());
+/// assert_eq!(id, ExternalId::Unknown("com.plexapp.agents.none://123".into()));
+/// assert_eq!(id.url("movie"), None);
+/// ```
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum ExternalId {
+    /// An IMDb ID, like `tt0111161`
+    Imdb(String),
+    /// A TMDB ID, like `278`
+    Tmdb(String),
+    /// A TVDB ID, like `190`
+    Tvdb(String),
+    /// Plex's own ID, like `movie/5d776825880197001ec967c6`
+    Plex(String),
+    /// Any other kind of ID, as plex sent it
+    Unknown(String),
+}
+
+impl ExternalId {
+    /// Name of the site the ID belongs to, if it has pages worth linking to
+    pub fn site(&self) -> Option<&'static str> {
+        match self {
+            ExternalId::Imdb(_) => Some("IMDb"),
+            ExternalId::Tmdb(_) => Some("TMDB"),
+            ExternalId::Tvdb(_) => Some("TVDB"),
+            ExternalId::Plex(_) | ExternalId::Unknown(_) => None,
+        }
+    }
+
+    /// Page for the item on its site, given the plex media type of the item, like `movie` or `episode`. [None] for
+    /// IDs of sites without pages, and for media types the site has no pages for
+    pub fn url(&self, media_type: &str) -> Option<String> {
+        match self {
+            ExternalId::Imdb(id) => Some(format!("https://www.imdb.com/title/{id}/")),
+            ExternalId::Tmdb(id) => {
+                // Seasons and episodes are only reachable through their show on TMDB
+                let kind = match media_type {
+                    "movie" => "movie",
+                    "show" => "tv",
+                    _ => return None,
+                };
+                Some(format!("https://www.themoviedb.org/{kind}/{id}"))
+            }
+            ExternalId::Tvdb(id) => {
+                let kind = match media_type {
+                    "movie" => "movie",
+                    "show" => "series",
+                    "season" => "season",
+                    "episode" => "episode",
+                    _ => return None,
+                };
+                Some(format!("https://thetvdb.com/dereferrer/{kind}/{id}"))
+            }
+            ExternalId::Plex(_) | ExternalId::Unknown(_) => None,
+        }
+    }
+}
+
+impl From<String> for ExternalId {
+    fn from(id: String) -> Self {
+        match id.split_once("://") {
+            Some(("imdb", rest)) => ExternalId::Imdb(rest.to_string()),
+            Some(("tmdb", rest)) => ExternalId::Tmdb(rest.to_string()),
+            Some(("tvdb", rest)) => ExternalId::Tvdb(rest.to_string()),
+            Some(("plex", rest)) => ExternalId::Plex(rest.to_string()),
+            _ => ExternalId::Unknown(id),
+        }
+    }
+}
+
+impl From<ExternalId> for String {
+    fn from(id: ExternalId) -> Self {
+        id.to_string()
+    }
+}
+
+impl std::fmt::Display for ExternalId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExternalId::Imdb(id) => write!(f, "imdb://{id}"),
+            ExternalId::Tmdb(id) => write!(f, "tmdb://{id}"),
+            ExternalId::Tvdb(id) => write!(f, "tvdb://{id}"),
+            ExternalId::Plex(id) => write!(f, "plex://{id}"),
+            ExternalId::Unknown(id) => f.write_str(id),
+        }
+    }
+}
+
+// The plex webhook docs say nothing of significance that guarantees the presence or absence of these fields
+//  To avoid errors, every field is optional in metadata, and errors resulting from missing data should be handled on a case-by-case basis
+/// The item an event is about, like a movie, an episode or a track
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    // Child info (directly describing this item)
+    /// Name of the item
+    pub title: Option<String>,
+    /// Name the item is sorted by, like the title without a leading "The"
+    pub title_sort: Option<String>,
+    /// Path of the item's poster on the server
+    pub thumb: Option<String>,
+    /// Path of the item on the server, like `/library/metadata/1936`
+    pub key: Option<String>,
+    /// Plex's own ID for the item, like `plex://movie/5d7768ba96b655001fdc0408`
+    pub guid: Option<String>,
+    /// The server's ID for the item
+    pub rating_key: Option<String>,
+    /// Plot summary
+    pub summary: Option<String>,
+    /// IDs of the item in external databases, see [Metadata::external_ids]
+    #[serde(rename = "Guid")]
+    pub external_links: Option<Vec<Link>>,
+    /// Kind of item, like `movie`, `episode` or `track`
+    #[serde(rename = "type")]
+    pub media_type: Option<String>,
+
+    // Miscellaneous extra info
+    /// Number of the item within its parent, like an episode's number in its season
+    pub index: Option<u64>,
+    /// Path of the item's background art on the server
+    pub art: Option<String>,
+    /// Times the item has been skipped
+    pub skip_count: Option<u64>,
+    /// Times the item has been played to the end
+    pub view_count: Option<u64>,
+    /// Audience score out of 10
+    pub audience_rating: Option<f32>,
+    /// Where the audience score is from, like `rottentomatoes://image.rating.upright`
+    pub audience_rating_image: Option<String>,
+    /// Kind of library the item is in, like `movie` or `show`
+    pub library_section_type: Option<String>,
+    /// Age rating, like `PG-13` or `TV-MA`
+    pub content_rating: Option<String>,
+    /// How far into the item playback is, in milliseconds
+    pub view_offset: Option<u64>,
+
+    // Credits info
+    /// Writers, in credit order
+    #[serde(rename = "Writer")]
+    pub writer: Option<Vec<Credit>>,
+    /// Directors, in credit order
+    #[serde(rename = "Director")]
+    pub director: Option<Vec<Credit>>,
+    /// Cast, in billing order
+    #[serde(rename = "Role")]
+    pub role: Option<Vec<Credit>>,
+    /// Producers, in credit order
+    #[serde(rename = "Producer")]
+    pub producer: Option<Vec<Credit>>,
+
+    // Times. Dates are written out, timestamps are in seconds since the unix epoch
+    /// Release or air date, like `1994-09-23`
+    pub originally_available_at: Option<String>,
+    /// When the item's metadata last changed
+    pub updated_at: Option<u64>,
+    /// When the item was last played
+    pub last_viewed_at: Option<u64>,
+    /// Length of the item, in milliseconds
+    pub duration: Option<u64>,
+    /// When the item was added to its library
+    pub added_at: Option<u64>,
+
+    // Parent info (if present)
+    /// The server's ID for the item's parent, like an episode's season or a track's album
+    pub parent_rating_key: Option<String>,
+    /// Number of the parent, like an episode's season number or a track's disc number
+    pub parent_index: Option<u64>,
+    /// Path of the parent on the server
+    pub parent_key: Option<String>,
+    /// Name of the parent
+    pub parent_title: Option<String>,
+    /// Plex's own ID for the parent
+    pub parent_guid: Option<String>,
+    /// Path of the parent's poster on the server
+    pub parent_thumb: Option<String>,
+
+    // Grandparent info (if present)
+    /// Path of the grandparent, like an episode's show or a track's artist, on the server
+    pub grandparent_key: Option<String>,
+    /// Name of the grandparent
+    pub grandparent_title: Option<String>,
+    /// Path of the grandparent's poster on the server
+    pub grandparent_thumb: Option<String>,
+    /// Path of the grandparent's theme music on the server
+    pub grandparent_theme: Option<String>,
+    /// Plex's own ID for the grandparent
+    pub grandparent_guid: Option<String>,
+    /// The server's ID for the grandparent
+    pub grandparent_rating_key: Option<String>,
+    /// Path of the grandparent's background art on the server
+    pub grandparent_art: Option<String>,
+
+    // Containing library info
+    /// Name of the library the item is in
+    pub library_section_title: Option<String>,
+    /// Path of the library on the server
+    pub library_section_key: Option<String>,
+    /// The server's ID for the library
+    #[serde(rename = "librarySectionID")]
+    pub library_section_id: Option<u32>,
+
+    /// Every other field plex sent, as it was sent
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+impl Metadata {
+    /// Every ID known for this item: plex's own from its guid, followed by any from external databases
+    pub fn external_ids(&self) -> Vec<ExternalId> {
+        let guid = self.guid.iter().map(|guid| ExternalId::from(guid.clone()));
+        let links = self.external_links.iter().flatten().map(|l| l.id.clone());
+        guid.chain(links).collect()
+    }
+}
+
+/// The JSON part of a webhook, describing what happened
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Payload {
+    /// What happened
+    pub event: Event,
+    /// Whether the event was caused by the account the webhook belongs to
+    #[serde(default)]
+    pub user: bool,
+    /// Whether the account the webhook belongs to owns the server
+    #[serde(default)]
+    pub owner: bool,
+    /// Who caused the event
+    #[serde(rename(deserialize = "Account"), alias = "account")]
+    pub account: Option<Account>,
+    /// Which server it happened on
+    #[serde(rename(deserialize = "Server"), alias = "server")]
+    pub server: Option<Server>,
+    /// What the item is played on, for playback events
+    #[serde(rename(deserialize = "Player"), alias = "player")]
+    pub player: Option<Player>,
+    /// The item the event is about, if any
+    #[serde(rename(deserialize = "Metadata"), alias = "metadata")]
+    pub metadata: Option<Metadata>,
+}
+
+impl Payload {
+    /// Name of the account that triggered this event, if known
+    pub fn account_name(&self) -> Option<&str> {
+        self.account.as_ref()?.title.as_deref()
+    }
+
+    /// Name of the server that sent this event, if known
+    pub fn server_name(&self) -> Option<&str> {
+        self.server.as_ref()?.title.as_deref()
+    }
+}
@@ -0,0 +1,309 @@
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, warn};
+
+use crate::models::{Event, Payload};
+
+/// A webhook request from a Plex server is comprised of two parts, a [Payload] and an optional thumbnail
+/// for certain events. The thumbnail is JPEG encoded, stored here in a [Vec].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlexWebhookRequest {
+    /// What happened
+    pub payload: Payload,
+    /// The item's thumbnail, for events that come with one
+    pub thumb: Option<Vec<u8>>,
+}
+
+/// Reasons a request can't be made sense of as a plex webhook. Each has an HTTP status to reply with, a short machine
+/// readable [WebhookError::kind] and a human readable [WebhookError::message]
+#[derive(Debug)]
+pub enum WebhookError {
+    /// The request isn't a multipart form, or has no boundary to split it on
+    UnsupportedMediaType,
+    /// The request body is larger than the limit it was read with
+    TooLarge {
+        /// The limit, in bytes
+        limit: u64,
+    },
+    /// The multipart form could not be read
+    Form(String),
+    /// There was no payload part in the form
+    MissingPayload,
+    /// The payload part was not valid JSON, or didn't match the models. `path` points at the offending field
+    InvalidPayload {
+        /// Where in the payload the problem is, like `Metadata.index`
+        path: String,
+        /// What the problem is
+        message: String,
+    },
+}
+
+impl WebhookError {
+    /// HTTP status code to reply with
+    pub fn status(&self) -> u16 {
+        match self {
+            WebhookError::UnsupportedMediaType => 415,
+            WebhookError::TooLarge { .. } => 413,
+            _ => 400,
+        }
+    }
+
+    /// Short machine readable name for the error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WebhookError::UnsupportedMediaType => "unsupported_media_type",
+            WebhookError::TooLarge { .. } => "payload_too_large",
+            WebhookError::Form(_) => "invalid_form",
+            WebhookError::MissingPayload => "missing_payload",
+            WebhookError::InvalidPayload { .. } => "invalid_payload",
+        }
+    }
+
+    /// Human readable description of the error
+    pub fn message(&self) -> String {
+        match self {
+            WebhookError::UnsupportedMediaType => "Request must be multipart/form-data".to_string(),
+            WebhookError::TooLarge { limit } => {
+                format!("Request body is larger than {limit} bytes")
+            }
+            WebhookError::Form(e) => format!("Failed to read multipart form: {e}"),
+            WebhookError::MissingPayload => "Form has no payload part".to_string(),
+            WebhookError::InvalidPayload { path, message } => {
+                format!("Invalid payload at {path}: {message}")
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message())
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+/// Parse the body of a request sent by a plex server, given the request's `Content-Type` header. This doesn't depend
+/// on any particular web framework, everything it needs is in the header and the body.
+///
+/// ```
+/// use plex_webhook::models::Event;
+/// use plex_webhook::webhook::{parse, WebhookError};
+///
+/// let body = "--boundary\r\n\
+///     Content-Disposition: form-data; name=\"payload\"\r\n\r\n\
+///     {\"event\": \"media.play\", \"Account\": {\"title\": \"someone\"}}\r\n\
+///     --boundary--\r\n";
+///
+/// let request = parse("multipart/form-data; boundary=boundary", body.as_bytes()).unwrap();
+/// assert_eq!(request.payload.event, Event::MediaPlay);
+/// assert_eq!(request.payload.account_name(), Some("someone"));
+/// assert!(request.thumb.is_none());
+///
+/// let error = parse("application/json", b"{}").unwrap_err();
+/// assert_eq!(error.status(), 415);
+/// ```
+pub fn parse(content_type: &str, body: &[u8]) -> Result<PlexWebhookRequest, WebhookError> {
+    let boundary = boundary(content_type).ok_or(WebhookError::UnsupportedMediaType)?;
+    let body = Bytes::copy_from_slice(body);
+    let mut form = multer::Multipart::new(
+        futures::stream::once(async move { Ok::<_, Infallible>(body) }),
+        boundary,
+    );
+
+    // The whole body is already in memory, so reading the form never actually has to wait
+    futures::executor::block_on(async {
+        let mut payload = None;
+        let mut thumb = None;
+        // Split parts of multipart form
+        while let Some(field) = form
+            .next_field()
+            .await
+            .map_err(|e| WebhookError::Form(e.to_string()))?
+        {
+            let name = field.name().map(str::to_string);
+            let value = field
+                .bytes()
+                .await
+                .map_err(|e| WebhookError::Form(e.to_string()))?;
+
+            match name.as_deref() {
+                Some("payload") => payload = Some(parse_payload(&value)?),
+                // Take the thumbnail and just shove it into a byte vector, dependent code may use it or not
+                Some("thumb") => thumb = Some(value.to_vec()),
+                s => warn!("Discarding unexpected form field {s:?}"),
+            }
+        }
+
+        match payload {
+            Some(payload) => Ok(PlexWebhookRequest { payload, thumb }),
+            None => Err(WebhookError::MissingPayload),
+        }
+    })
+}
+
+/// Boundary of a `multipart/form-data` content type, if that's what it is
+fn boundary(content_type: &str) -> Option<String> {
+    let mime: mime::Mime = content_type.parse().ok()?;
+    if mime.type_() != mime::MULTIPART || mime.subtype() != mime::FORM_DATA {
+        return None;
+    }
+    mime.get_param(mime::BOUNDARY).map(|b| b.to_string())
+}
+
+fn parse_payload(value: &[u8]) -> Result<Payload, WebhookError> {
+    // Parse payload using models and serde_json, keeping track of where in the payload any error is
+    let de = &mut serde_json::Deserializer::from_slice(value);
+    let payload: Payload = serde_path_to_error::deserialize(de).map_err(|e| {
+        error!("Failed to parse request payload with {}", e);
+        WebhookError::InvalidPayload {
+            path: e.path().to_string(),
+            message: e.inner().to_string(),
+        }
+    })?;
+
+    // Keep going with events we don't know about, routing rules may still want them
+    if let Event::Unknown(name) = &payload.event {
+        warn!("Received unknown plex event {name}");
+    }
+
+    // Warn if metadata parsing is wrong, necessary since the format may change and was gleaned from reverse-engineering in the first place
+    if let Some(metadata) = payload.metadata.as_ref() {
+        if !metadata.extra.is_empty() {
+            warn!("{} extra fields in metadata", metadata.extra.len());
+            debug!("Extra metadata fields: {:#?}", metadata.extra);
+        }
+    }
+
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=boundary";
+
+    /// A form with these parts, each a name, an optional file name and the value
+    fn form(parts: &[(&str, Option<&str>, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, filename, value) in parts {
+            body.extend_from_slice(b"--boundary\r\n");
+            let disposition = match filename {
+                Some(filename) => {
+                    format!("Content-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\n\r\n")
+                }
+                None => format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n"),
+            };
+            body.extend_from_slice(disposition.as_bytes());
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"--boundary--\r\n");
+        body
+    }
+
+    #[test]
+    fn payload_and_thumbnail() {
+        let thumb = [0xFF, 0xD8, 0xFF, 0x00, b'\r', b'\n', 0xD9];
+        let body = form(&[
+            (
+                "payload",
+                None,
+                br#"{"event": "library.new", "Metadata": {"title": "Heat"}}"#,
+            ),
+            ("thumb", Some("thumb.jpg"), &thumb),
+            ("unexpected", None, b"ignored"),
+        ]);
+
+        let request = parse(CONTENT_TYPE, &body).unwrap();
+        assert_eq!(request.payload.event, Event::LibraryNew);
+        assert_eq!(
+            request.payload.metadata.unwrap().title.as_deref(),
+            Some("Heat")
+        );
+        assert_eq!(request.thumb.as_deref(), Some(&thumb[..]));
+    }
+
+    #[test]
+    fn unknown_events_are_kept() {
+        let body = form(&[("payload", None, br#"{"event": "library.brand.new"}"#)]);
+        let request = parse(CONTENT_TYPE, &body).unwrap();
+        assert_eq!(
+            request.payload.event,
+            Event::Unknown("library.brand.new".into())
+        );
+    }
+
+    #[test]
+    fn not_a_multipart_form() {
+        let body = form(&[("payload", None, br#"{"event": "media.play"}"#)]);
+        for content_type in [
+            "",
+            "application/json",
+            "multipart/mixed; boundary=boundary",
+            // A form without a boundary can't be split
+            "multipart/form-data",
+        ] {
+            let error = parse(content_type, &body).unwrap_err();
+            assert!(
+                matches!(error, WebhookError::UnsupportedMediaType),
+                "{content_type}: {error:?}"
+            );
+            assert_eq!(error.status(), 415);
+            assert_eq!(error.kind(), "unsupported_media_type");
+        }
+    }
+
+    #[test]
+    fn missing_payload() {
+        let body = form(&[("thumb", Some("thumb.jpg"), b"jpeg")]);
+        let error = parse(CONTENT_TYPE, &body).unwrap_err();
+        assert!(matches!(error, WebhookError::MissingPayload), "{error:?}");
+        assert_eq!(error.status(), 400);
+        assert_eq!(error.kind(), "missing_payload");
+
+        let error = parse(CONTENT_TYPE, b"--boundary--\r\n").unwrap_err();
+        assert!(matches!(error, WebhookError::MissingPayload), "{error:?}");
+    }
+
+    #[test]
+    fn unreadable_form() {
+        // Cut off partway through a part
+        let body =
+            b"--boundary\r\nContent-Disposition: form-data; name=\"payload\"\r\n\r\n{\"event\"";
+        let error = parse(CONTENT_TYPE, body).unwrap_err();
+        assert!(matches!(error, WebhookError::Form(_)), "{error:?}");
+        assert_eq!(error.status(), 400);
+        assert_eq!(error.kind(), "invalid_form");
+    }
+
+    #[test]
+    fn invalid_payload_points_at_the_field() {
+        for (payload, expected_path) in [
+            (
+                &br#"{"event": "media.play", "Metadata": {"duration": "long"}}"#[..],
+                "Metadata.duration",
+            ),
+            (br#"{"event": 5}"#, "event"),
+            // Missing the event, which is only noticed once the whole object has been read
+            (br#"{"Account": {}}"#, "."),
+            // Cut off before the object ends, so there's no telling which field it was in
+            (br#"{"event": "media.play""#, "?"),
+            (b"not json", "."),
+        ] {
+            let body = form(&[("payload", None, payload)]);
+            let error = parse(CONTENT_TYPE, &body).unwrap_err();
+            assert_eq!(error.status(), 400);
+            assert_eq!(error.kind(), "invalid_payload");
+            match error {
+                WebhookError::InvalidPayload { path, .. } => {
+                    assert_eq!(path, expected_path, "{}", String::from_utf8_lossy(payload));
+                }
+                error => panic!("Expected an invalid payload, got {error:?}"),
+            }
+        }
+    }
+}
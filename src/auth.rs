@@ -0,0 +1,252 @@
+//! Access control for the webhook endpoint. Plex can't send custom headers with its webhooks, so the shared secret is
+//! taken from the URL instead, either as a path segment (`/plex/<secret>`) or a query parameter (`/plex?token=<secret>`).
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use color_eyre::{eyre::eyre, Result};
+use ipnet::IpNet;
+use tracing::warn;
+use warp::{path::Tail, Filter, Rejection};
+
+/// The request did not carry the right secret
+#[derive(Debug)]
+pub struct Unauthorized;
+impl warp::reject::Reject for Unauthorized {}
+
+/// The request came from an address outside the allowlist
+#[derive(Debug)]
+pub struct Forbidden;
+impl warp::reject::Reject for Forbidden {}
+
+/// Which requests may post to the webhook endpoint
+#[derive(Debug, Default)]
+pub struct Auth {
+    secret: Option<String>,
+    allowed_sources: Vec<IpNet>,
+}
+
+impl Auth {
+    /// Sources may be networks in CIDR notation or single addresses. With no secret and no sources, everything is
+    /// allowed.
+    pub fn new(secret: Option<String>, allowed_sources: &[String]) -> Result<Self> {
+        let allowed_sources = allowed_sources
+            .iter()
+            .map(|source| {
+                source
+                    .parse::<IpNet>()
+                    .or_else(|_| source.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| eyre!("Invalid allowed source address {}", source))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            secret: secret.filter(|s| !s.is_empty()),
+            allowed_sources,
+        })
+    }
+
+    /// True if this doesn't restrict anything
+    pub fn is_open(&self) -> bool {
+        self.secret.is_none() && self.allowed_sources.is_empty()
+    }
+
+    fn check_source(&self, remote: Option<SocketAddr>) -> Result<(), Rejection> {
+        if self.allowed_sources.is_empty() {
+            return Ok(());
+        }
+
+        // Dual stack listeners see IPv4 clients as IPv4-mapped IPv6 addresses, which IPv4 networks don't contain
+        match remote.map(|addr| addr.ip().to_canonical()) {
+            Some(ip) if self.allowed_sources.iter().any(|n| n.contains(&ip)) => Ok(()),
+            _ => {
+                warn!("Rejected webhook from {remote:?}, not in the allowed sources");
+                Err(warp::reject::custom(Forbidden))
+            }
+        }
+    }
+
+    fn check_secret(
+        &self,
+        remote: Option<SocketAddr>,
+        given: Option<&str>,
+    ) -> Result<(), Rejection> {
+        let secret = match &self.secret {
+            Some(secret) => secret,
+            None => return Ok(()),
+        };
+
+        match given {
+            Some(given) if constant_time_eq(given.as_bytes(), secret.as_bytes()) => Ok(()),
+            _ => {
+                warn!("Rejected webhook from {remote:?} with a missing or incorrect secret");
+                Err(warp::reject::custom(Unauthorized))
+            }
+        }
+    }
+}
+
+/// Filter rejecting requests that don't pass [Auth]. Consumes the rest of the path, which is where the secret goes
+pub fn authorize(auth: Arc<Auth>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::path::tail())
+        .and(
+            warp::query::<HashMap<String, String>>()
+                .or(warp::any().map(HashMap::new))
+                .unify(),
+        )
+        .and_then(
+            move |remote: Option<SocketAddr>, tail: Tail, query: HashMap<String, String>| {
+                let auth = auth.clone();
+                async move {
+                    auth.check_source(remote)?;
+
+                    let given = match tail.as_str().trim_matches('/') {
+                        "" => query.get("token").map(String::as_str),
+                        segment => Some(segment),
+                    };
+                    auth.check_secret(remote, given)
+                }
+            },
+        )
+        .untuple_one()
+}
+
+/// Compare secrets without bailing at the first difference, so timing doesn't give away how much of a guess is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use warp::http::StatusCode;
+
+    fn auth(secret: Option<&str>, sources: &[&str]) -> Arc<Auth> {
+        let sources: Vec<String> = sources.iter().map(|s| s.to_string()).collect();
+        Arc::new(Auth::new(secret.map(str::to_string), &sources).unwrap())
+    }
+
+    /// Status a request gets from the endpoint, with rejections turned into replies like the server does
+    async fn status(auth: Arc<Auth>, remote: &str, path: &str) -> StatusCode {
+        let filter = warp::path("plex")
+            .and(authorize(auth))
+            .map(warp::reply)
+            .recover(crate::handle_rejection);
+
+        warp::test::request()
+            .method("POST")
+            .remote_addr(remote.parse().unwrap())
+            .path(path)
+            .reply(&filter)
+            .await
+            .status()
+    }
+
+    #[tokio::test]
+    async fn secret_in_the_path_or_query() {
+        let auth = auth(Some("hunter2"), &[]);
+        let remote = "192.0.2.1:5000";
+
+        assert_eq!(
+            status(auth.clone(), remote, "/plex/hunter2").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(auth.clone(), remote, "/plex/hunter2/").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(auth.clone(), remote, "/plex?token=hunter2").await,
+            StatusCode::OK
+        );
+
+        for path in [
+            "/plex",
+            "/plex/hunter3",
+            "/plex/hunter",
+            "/plex?token=",
+            "/plex?token=nope",
+        ] {
+            assert_eq!(
+                status(auth.clone(), remote, path).await,
+                StatusCode::UNAUTHORIZED,
+                "{path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn sources_outside_the_allowlist_are_forbidden() {
+        let auth = auth(None, &["10.0.0.0/8", "192.0.2.7", "fd00::/8"]);
+
+        for remote in ["10.1.2.3:1", "192.0.2.7:1", "[fd00::1]:1"] {
+            assert_eq!(
+                status(auth.clone(), remote, "/plex").await,
+                StatusCode::OK,
+                "{remote}"
+            );
+        }
+        for remote in ["11.0.0.1:1", "192.0.2.8:1", "[fe80::1]:1"] {
+            assert_eq!(
+                status(auth.clone(), remote, "/plex").await,
+                StatusCode::FORBIDDEN,
+                "{remote}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn ipv4_mapped_addresses_match_ipv4_sources() {
+        let auth = auth(None, &["10.0.0.0/8"]);
+
+        assert_eq!(
+            status(auth.clone(), "[::ffff:10.1.2.3]:1", "/plex").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(auth, "[::ffff:11.1.2.3]:1", "/plex").await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn source_is_checked_before_the_secret() {
+        let auth = auth(Some("hunter2"), &["10.0.0.0/8"]);
+
+        assert_eq!(
+            status(auth.clone(), "11.0.0.1:1", "/plex/hunter2").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(auth.clone(), "10.0.0.1:1", "/plex/wrong").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(auth, "10.0.0.1:1", "/plex/hunter2").await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn open_without_secret_or_sources() {
+        assert!(Auth::new(None, &[]).unwrap().is_open());
+        assert!(Auth::new(Some(String::new()), &[]).unwrap().is_open());
+        assert!(!auth(Some("s"), &[]).is_open());
+        assert!(Auth::new(None, &["not an address".to_string()]).is_err());
+    }
+
+    #[test]
+    fn constant_time_comparison() {
+        assert!(constant_time_eq(b"hunter2", b"hunter2"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"hunter2", b"hunter3"));
+        assert!(!constant_time_eq(b"Hunter2", b"hunter2"));
+        // A prefix of the secret, or the secret with more after it, isn't a match
+        assert!(!constant_time_eq(b"hunter", b"hunter2"));
+        assert!(!constant_time_eq(b"hunter22", b"hunter2"));
+        assert!(!constant_time_eq(b"", b"hunter2"));
+    }
+}
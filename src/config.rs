@@ -0,0 +1,826 @@
+//! Loads the optional TOML configuration file, which defines named discord destinations and the rules for routing
+//! plex events to them.
+//!
+//! ```toml
+//! port = 8001
+//! throttle = 30
+//! max_delay = 300
+//! aggregate = "edit"
+//! rematch = "delete"
+//! secret = "some long random string"
+//! allowed_sources = ["192.168.1.0/24"]
+//! dashboard = "now-playing"
+//!
+//! [destinations.movies]
+//! url = "https://discord.com/api/webhooks/..."
+//!
+//! [destinations.shows]
+//! url = "https://discord.com/api/webhooks/..."
+//!
+//! [destinations.now-playing]
+//! url = "https://discord.com/api/webhooks/..."
+//!
+//! [destinations.admin]
+//! url = "https://discord.com/api/webhooks/..."
+//!
+//! [[routes]]
+//! destinations = ["movies"]
+//! events = ["library.new"]
+//! media_types = ["movie"]
+//!
+//! [[routes]]
+//! destinations = ["movies"]
+//! events = ["library.new"]
+//! library_sections = ["4K Movies"]
+//! mention_roles = ["123456789012345678"]
+//!
+//! [[routes]]
+//! destinations = ["shows"]
+//! events = ["library.new"]
+//! media_types = ["episode"]
+//! forum = true
+//! fields = ["runtime", "rated", "released"]
+//!
+//! [[routes]]
+//! destinations = ["admin"]
+//! events = ["admin.database.backup", "admin.database.corrupted", "device.new"]
+//! mention_users = ["234567890123456789"]
+//! username = "{server}"
+//! avatar_url = "https://example.com/plex.png"
+//! ```
+//!
+//! Every filter in a route is optional, and an empty or missing filter matches anything. An event is sent to the
+//! destinations of every route that matches it, but only once to each destination. If several matching routes share
+//! a destination, the first of them decides how the message looks there.
+//!
+//! `username` and `avatar_url` override the webhook's own name and avatar. `{server}` and `{account}` in them are
+//! replaced by the names of the plex server and account the event came from, and if either isn't known the webhook's
+//! default is used instead.
+//!
+//! `mention_roles` and `mention_users` are discord IDs to ping with every message the route sends. Nothing else in a
+//! message can ping anyone, not even an `@everyone` that finds its way into a title. When several matching routes share
+//! a destination, the mentions of all of them are combined.
+//!
+//! `fields` picks which details new movies and episodes are shown with, out of `director`, `starring`, `runtime`,
+//! `rated`, `audience_score`, `released` and `links`. Links to the item's IMDb, TMDB and TVDB pages are shown for
+//! anything newly added that has them, with the title linking to the first, and both go together under `links`.
+//! Without `fields` everything is shown, and `fields = []` shows none.
+//!
+//! Routes to a webhook of a forum channel must set `forum`, which makes one post per show (or per item, for anything
+//! that isn't part of a show) and sends every later episode of the show to the same post. Alternatively `thread_id`
+//! sends everything the route matches to an existing thread or post.
+//!
+//! `max_delay` caps how long siblings are held back in `hold` mode, counted from the first of them. Once it passes the
+//! group is sent even if siblings are still arriving, and any later ones start a new group. In `edit` mode it caps how
+//! long a message keeps being edited to add siblings, after which later ones get a new message.
+//!
+//! `rematch` decides what happens to the notification for a newly added item when plex sends `library.new` for it
+//! again, as it does after the item is re-matched: `keep` it, `edit` it to show the item as it is now, or `delete` it
+//! and send a new one. Items are recognized by their rating key, and notifications are remembered across restarts for
+//! items sent on their own or held back with their siblings, but not for siblings combined in `edit` mode. Only a
+//! notification showing exactly the items sent again is replaced, one for a larger group is redrawn without them.
+//!
+//! `dashboard` names a destination to keep a "now playing" message up to date in for each plex server, as playback
+//! events come in. It works independently of the routes.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use color_eyre::{eyre::eyre, eyre::WrapErr, Result};
+use discord_webhook::webhook::{AllowedMention, WebhookRequest};
+use plex_webhook::models::{Event, Payload};
+use serde::Deserialize;
+
+use crate::render::{display_title, Field, Notification};
+
+/// Contents of the configuration file. Settings given on the command line take precedence over these
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub port: Option<u16>,
+    pub throttle: Option<u32>,
+    pub max_delay: Option<u32>,
+    pub aggregate: Option<Aggregate>,
+    pub rematch: Option<Rematch>,
+    #[serde(default)]
+    pub save_requests: bool,
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub allowed_sources: Vec<String>,
+    /// Destination to keep now playing messages in
+    pub dashboard: Option<String>,
+    #[serde(default)]
+    pub destinations: HashMap<String, Destination>,
+    #[serde(default)]
+    pub routes: Vec<Route>,
+}
+
+/// How siblings (like episodes of a season) that arrive within the throttle period are combined into one message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregate {
+    /// Hold siblings back until none have arrived for the throttle period, then send them together
+    #[default]
+    Hold,
+    /// Send the first sibling right away, and edit its message as more arrive within the throttle period
+    Edit,
+}
+
+/// What to do with the earlier notification for an item that plex adds again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ArgEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Rematch {
+    /// Leave it, and send a new notification as usual
+    #[default]
+    Keep,
+    /// Edit it to show the item as it is now, instead of sending a new notification
+    Edit,
+    /// Delete it, and send a new notification as usual
+    Delete,
+}
+
+impl FileConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config file {}", path.display()))?;
+
+        toml::from_str(&text)
+            .wrap_err_with(|| format!("Failed to parse config file {}", path.display()))
+    }
+}
+
+/// A discord webhook that notifications may be sent to
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Destination {
+    pub url: String,
+}
+
+/// Sends events that pass all of its filters to its destinations
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Route {
+    /// Names of entries in the destinations table
+    pub destinations: Vec<String>,
+    /// Plex event names, like `library.new`. Event names not otherwise known to the relay work too
+    #[serde(default)]
+    pub events: Vec<Event>,
+    /// Library sections, by title or ID
+    #[serde(default)]
+    pub library_sections: Vec<String>,
+    /// Plex media types, like `movie`, `episode` or `track`
+    #[serde(default)]
+    pub media_types: Vec<String>,
+    /// Plex servers, by UUID
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Plex accounts, by title or ID
+    #[serde(default)]
+    pub accounts: Vec<String>,
+
+    /// Name to post as, instead of the webhook's own
+    pub username: Option<String>,
+    /// Avatar to post with, instead of the webhook's own
+    pub avatar_url: Option<String>,
+    /// IDs of discord roles to ping
+    #[serde(default)]
+    pub mention_roles: Vec<String>,
+    /// IDs of discord users to ping
+    #[serde(default)]
+    pub mention_users: Vec<String>,
+
+    /// ID of an existing thread or forum post in the destination's channel to post in
+    pub thread_id: Option<String>,
+    /// The destination is a forum channel, post in it with a post per show
+    #[serde(default)]
+    pub forum: bool,
+
+    /// Details to show for new movies and episodes, all of them if not given
+    pub fields: Option<Vec<Field>>,
+}
+
+impl Route {
+    /// Where this route's messages for this event go, if in a thread
+    fn thread(&self, payload: &Payload) -> Option<Thread> {
+        if let Some(id) = &self.thread_id {
+            return Some(Thread::Existing(id.clone()));
+        }
+        if !self.forum {
+            return None;
+        }
+
+        // Episodes go in their show's post, anything else gets a post of its own. Tracks have a grandparent too, their
+        // artist, but an artist's albums are better off apart
+        let metadata = payload.metadata.as_ref();
+        let key = metadata
+            .filter(|m| m.media_type.as_deref() == Some("episode"))
+            .and_then(|m| m.grandparent_rating_key.clone());
+        let name = match (&key, metadata) {
+            (Some(_), Some(m)) => m.grandparent_title.clone(),
+            (None, Some(m)) => Some(display_title(m)),
+            _ => None,
+        };
+
+        // Discord limits post names to 100 characters
+        let name = name.unwrap_or_else(|| payload.event.to_string());
+        Some(Thread::Forum {
+            key,
+            name: name.chars().take(100).collect(),
+        })
+    }
+
+    pub fn matches(&self, payload: &Payload) -> bool {
+        let metadata = payload.metadata.as_ref();
+
+        let section_matches = |section: &String| {
+            metadata.is_some_and(|m| {
+                m.library_section_title.as_ref() == Some(section)
+                    || m.library_section_id.map(|id| id.to_string()).as_ref() == Some(section)
+            })
+        };
+        let media_type_matches =
+            |t: &String| metadata.is_some_and(|m| m.media_type.as_ref() == Some(t));
+        let account_matches = |a: &String| {
+            payload.account.as_ref().is_some_and(|account| {
+                account.title.as_ref() == Some(a)
+                    || account.id.map(|id| id.to_string()).as_ref() == Some(a)
+            })
+        };
+        let server_uuid = payload.server.as_ref().and_then(|s| s.uuid.as_ref());
+
+        (self.events.is_empty() || self.events.contains(&payload.event))
+            && (self.library_sections.is_empty()
+                || self.library_sections.iter().any(section_matches))
+            && (self.media_types.is_empty() || self.media_types.iter().any(media_type_matches))
+            && (self.servers.is_empty()
+                || server_uuid.is_some_and(|uuid| self.servers.contains(uuid)))
+            && (self.accounts.is_empty() || self.accounts.iter().any(account_matches))
+    }
+}
+
+/// Where an event should be sent, and how it should appear there
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Target {
+    pub url: String,
+    pub username: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Roles and users to ping, which are also the only ones allowed to be pinged
+    pub mentions: AllowedMention,
+    /// Where in the destination's channel to post, if not in the channel itself
+    pub thread: Option<Thread>,
+    /// Details to keep in embeds, all of them if [None]
+    pub fields: Option<Vec<Field>>,
+}
+
+/// A thread, or forum post, that a target's messages go in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Thread {
+    /// An existing thread, by ID
+    Existing(String),
+    /// The forum post made for everything with this key, created with this name if there isn't one yet. Without a
+    /// key, a new post is made every time
+    Forum { key: Option<String>, name: String },
+}
+
+impl Target {
+    /// Dress a notification up as a request the way this target wants it, with the details it picked and any mentions
+    /// ahead of the content
+    pub fn apply(&self, notification: &Notification) -> WebhookRequest {
+        let mut request = WebhookRequest {
+            content: notification.content.clone(),
+            embeds: notification
+                .embeds
+                .iter()
+                .map(|rendered| rendered.embed(self.fields.as_deref()))
+                .collect(),
+            ..Default::default()
+        };
+        request.metadata.username = self.username.clone();
+        request.metadata.avatar_url = self.avatar_url.clone();
+
+        let mentions = self.mentions.mentions();
+        if !mentions.is_empty() {
+            request.content = Some(match request.content {
+                Some(content) => format!("{mentions} {content}"),
+                None => mentions,
+            });
+        }
+        request.metadata.allowed_mentions = self.mentions.clone();
+
+        request
+    }
+}
+
+/// Resolved routing table, combining the configuration file with any webhook URLs given on the command line
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    destinations: HashMap<String, Destination>,
+    routes: Vec<Route>,
+    dashboard: Option<String>,
+}
+
+impl Router {
+    /// Each URL given on the command line gets a destination of its own, with a route that sends it everything
+    pub fn new(file: &FileConfig, cli_urls: &[String]) -> Result<Self> {
+        let mut destinations = file.destinations.clone();
+        let mut routes = file.routes.clone();
+
+        for (n, url) in cli_urls.iter().enumerate() {
+            let name = format!("cli-{n}");
+            destinations.insert(name.clone(), Destination { url: url.clone() });
+            routes.push(Route {
+                destinations: vec![name],
+                ..Default::default()
+            });
+        }
+
+        // Catch typos at startup rather than silently dropping notifications
+        for route in &routes {
+            if let Some(missing) = route
+                .destinations
+                .iter()
+                .find(|d| !destinations.contains_key(*d))
+            {
+                return Err(eyre!("Route refers to undefined destination {}", missing));
+            }
+
+            // Names like @admins would just show up as text, mentions only work by ID
+            if let Some(bad) = route
+                .mention_roles
+                .iter()
+                .chain(&route.mention_users)
+                .find(|id| id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()))
+            {
+                return Err(eyre!("Route mentions {}, which is not a discord ID", bad));
+            }
+
+            if route.forum && route.thread_id.is_some() {
+                return Err(eyre!(
+                    "Route to {} can't both post in a thread and make forum posts",
+                    route.destinations.join(", ")
+                ));
+            }
+        }
+
+        if let Some(dashboard) = &file.dashboard {
+            if !destinations.contains_key(dashboard) {
+                return Err(eyre!(
+                    "Dashboard refers to undefined destination {}",
+                    dashboard
+                ));
+            }
+        }
+
+        Ok(Self {
+            destinations,
+            routes,
+            dashboard: file.dashboard.clone(),
+        })
+    }
+
+    /// Destination to keep now playing messages in, if any
+    pub fn dashboard(&self) -> Option<&Destination> {
+        // Existence was checked when the router was built
+        self.dashboard.as_ref().map(|name| &self.destinations[name])
+    }
+
+    /// Every destination this event should be sent to, in the order of the routes that matched it. Each destination
+    /// appears at most once.
+    pub fn targets(&self, payload: &Payload) -> Vec<Target> {
+        let mut targets: Vec<(&str, Target)> = Vec::new();
+
+        for route in self.routes.iter().filter(|r| r.matches(payload)) {
+            for name in &route.destinations {
+                if let Some((_, target)) = targets.iter_mut().find(|(seen, _)| seen == name) {
+                    // Everyone who asked to be pinged about this gets pinged, even if another route came first
+                    let mentions = &mut target.mentions;
+                    for role in &route.mention_roles {
+                        if !mentions.roles.contains(role) {
+                            mentions.roles.push(role.clone());
+                        }
+                    }
+                    for user in &route.mention_users {
+                        if !mentions.users.contains(user) {
+                            mentions.users.push(user.clone());
+                        }
+                    }
+                    continue;
+                }
+
+                // Existence was checked when the router was built
+                targets.push((
+                    name,
+                    Target {
+                        url: self.destinations[name].url.clone(),
+                        username: route.username.as_deref().and_then(|u| expand(u, payload)),
+                        avatar_url: route.avatar_url.as_deref().and_then(|a| expand(a, payload)),
+                        mentions: AllowedMention::only(
+                            route.mention_roles.clone(),
+                            route.mention_users.clone(),
+                        ),
+                        thread: route.thread(payload),
+                        fields: route.fields.clone(),
+                    },
+                ));
+            }
+        }
+
+        targets.into_iter().map(|(_, target)| target).collect()
+    }
+}
+
+/// Fill in the placeholders of a route's username or avatar, or [None] if the payload doesn't have what they need
+fn expand(template: &str, payload: &Payload) -> Option<String> {
+    let mut value = template.to_string();
+
+    for (placeholder, replacement) in [
+        ("{server}", payload.server_name()),
+        ("{account}", payload.account_name()),
+    ] {
+        if value.contains(placeholder) {
+            value = value.replace(placeholder, replacement?);
+        }
+    }
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(metadata: serde_json::Value) -> Payload {
+        serde_json::from_value(serde_json::json!({
+            "event": "library.new",
+            "Metadata": metadata,
+        }))
+        .unwrap()
+    }
+
+    fn episode() -> Payload {
+        payload(serde_json::json!({
+            "type": "episode",
+            "title": "Pilot",
+            "index": 1,
+            "parentIndex": 1,
+            "grandparentTitle": "Breaking Bad",
+            "grandparentRatingKey": "2048",
+        }))
+    }
+
+    fn destinations(names: &[&str]) -> HashMap<String, Destination> {
+        names
+            .iter()
+            .map(|name| {
+                let url = format!("https://discord.com/api/webhooks/{name}/token");
+                (name.to_string(), Destination { url })
+            })
+            .collect()
+    }
+
+    fn router(routes: Vec<Route>) -> Router {
+        let file = FileConfig {
+            destinations: destinations(&["movies", "shows", "admin"]),
+            routes,
+            ..Default::default()
+        };
+        Router::new(&file, &[]).unwrap()
+    }
+
+    fn to(destinations: &[&str]) -> Route {
+        Route {
+            destinations: destinations.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn urls(targets: &[Target]) -> Vec<&str> {
+        targets.iter().map(|t| t.url.as_str()).collect()
+    }
+
+    /// A movie from the "Films" library, section 1, played by account 7 (elan) on server abc
+    fn played_movie() -> Payload {
+        serde_json::from_value(serde_json::json!({
+            "event": "media.play",
+            "Account": { "id": 7, "title": "elan" },
+            "Server": { "title": "Office", "uuid": "abc" },
+            "Metadata": {
+                "type": "movie",
+                "title": "Heat",
+                "librarySectionTitle": "Films",
+                "librarySectionID": 1,
+            },
+        }))
+        .unwrap()
+    }
+
+    /// The example configuration in this module's documentation
+    fn documented_example() -> String {
+        include_str!("config.rs")
+            .lines()
+            .skip_while(|line| *line != "//! ```toml")
+            .skip(1)
+            .take_while(|line| *line != "//! ```")
+            .map(|line| {
+                line.strip_prefix("//! ")
+                    .unwrap_or(line.trim_start_matches("//!"))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn routes_without_filters_match_anything() {
+        assert!(Route::default().matches(&played_movie()));
+        assert!(Route::default().matches(&episode()));
+    }
+
+    #[test]
+    fn routes_match_events() {
+        let route = Route {
+            events: vec![Event::MediaPlay, Event::MediaStop],
+            ..Default::default()
+        };
+        assert!(route.matches(&played_movie()));
+        assert!(!route.matches(&episode()));
+    }
+
+    #[test]
+    fn routes_match_library_sections_by_title_or_id() {
+        let section = |s: &str| Route {
+            library_sections: vec![s.into()],
+            ..Default::default()
+        };
+        assert!(section("Films").matches(&played_movie()));
+        assert!(section("1").matches(&played_movie()));
+        assert!(!section("TV").matches(&played_movie()));
+        assert!(!section("2").matches(&played_movie()));
+
+        // Without metadata there's no section to match
+        let backup: Payload =
+            serde_json::from_value(serde_json::json!({ "event": "admin.database.backup" }))
+                .unwrap();
+        assert!(!section("Films").matches(&backup));
+    }
+
+    #[test]
+    fn routes_match_media_types() {
+        let route = Route {
+            media_types: vec!["episode".into(), "track".into()],
+            ..Default::default()
+        };
+        assert!(route.matches(&episode()));
+        assert!(!route.matches(&played_movie()));
+    }
+
+    #[test]
+    fn routes_match_servers_by_uuid() {
+        let server = |s: &str| Route {
+            servers: vec![s.into()],
+            ..Default::default()
+        };
+        assert!(server("abc").matches(&played_movie()));
+        assert!(!server("Office").matches(&played_movie()));
+        assert!(!server("abc").matches(&episode()));
+    }
+
+    #[test]
+    fn routes_match_accounts_by_title_or_id() {
+        let account = |a: &str| Route {
+            accounts: vec![a.into()],
+            ..Default::default()
+        };
+        assert!(account("elan").matches(&played_movie()));
+        assert!(account("7").matches(&played_movie()));
+        assert!(!account("friend").matches(&played_movie()));
+        assert!(!account("elan").matches(&episode()));
+    }
+
+    #[test]
+    fn every_filter_has_to_match() {
+        let route = Route {
+            events: vec![Event::MediaPlay],
+            media_types: vec!["episode".into()],
+            ..Default::default()
+        };
+        assert!(!route.matches(&played_movie()));
+        assert!(!route.matches(&episode()));
+    }
+
+    #[test]
+    fn each_destination_is_targeted_once() {
+        let router = router(vec![
+            Route {
+                username: Some("First".into()),
+                mention_roles: vec!["1".into()],
+                ..to(&["movies", "admin"])
+            },
+            // Doesn't match, so it doesn't get a say
+            Route {
+                events: vec![Event::LibraryNew],
+                mention_roles: vec!["9".into()],
+                ..to(&["shows"])
+            },
+            Route {
+                username: Some("Second".into()),
+                mention_roles: vec!["1".into(), "2".into()],
+                mention_users: vec!["3".into()],
+                ..to(&["admin", "shows"])
+            },
+        ]);
+
+        let targets = router.targets(&played_movie());
+        assert_eq!(
+            urls(&targets),
+            [
+                "https://discord.com/api/webhooks/movies/token",
+                "https://discord.com/api/webhooks/admin/token",
+                "https://discord.com/api/webhooks/shows/token",
+            ]
+        );
+
+        // The first route to reach a destination decides how it looks, but everyone gets their mentions
+        let admin = &targets[1];
+        assert_eq!(admin.username.as_deref(), Some("First"));
+        assert_eq!(admin.mentions.roles, ["1", "2"]);
+        assert_eq!(admin.mentions.users, ["3"]);
+        assert_eq!(targets[0].mentions.roles, ["1"]);
+        assert_eq!(targets[2].username.as_deref(), Some("Second"));
+    }
+
+    #[test]
+    fn command_line_urls_get_everything() {
+        let router = Router::new(
+            &FileConfig::default(),
+            &["https://discord.com/api/webhooks/1/a".into()],
+        )
+        .unwrap();
+        assert_eq!(
+            urls(&router.targets(&episode())),
+            ["https://discord.com/api/webhooks/1/a"]
+        );
+    }
+
+    #[test]
+    fn mistakes_are_caught_when_the_router_is_built() {
+        let build = |routes: Vec<Route>, dashboard: Option<&str>| {
+            let file = FileConfig {
+                destinations: destinations(&["movies"]),
+                routes,
+                dashboard: dashboard.map(str::to_string),
+                ..Default::default()
+            };
+            Router::new(&file, &[]).map(|_| ())
+        };
+
+        assert!(build(vec![to(&["movies"])], Some("movies")).is_ok());
+        assert!(build(vec![to(&["films"])], None).is_err());
+        assert!(build(Vec::new(), Some("now-playing")).is_err());
+        let named_mention = Route {
+            mention_roles: vec!["@admins".into()],
+            ..to(&["movies"])
+        };
+        assert!(build(vec![named_mention], None).is_err());
+        let thread_and_forum = Route {
+            thread_id: Some("42".into()),
+            ..forum()
+        };
+        assert!(build(
+            vec![Route {
+                destinations: vec!["movies".into()],
+                ..thread_and_forum
+            }],
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn documented_example_works() {
+        let file: FileConfig = toml::from_str(&documented_example()).unwrap();
+        assert_eq!(file.port, Some(8001));
+        assert_eq!(file.aggregate, Some(Aggregate::Edit));
+        assert_eq!(file.rematch, Some(Rematch::Delete));
+        let router = Router::new(&file, &[]).unwrap();
+        assert!(router.dashboard().is_some());
+
+        // Episodes go to one forum post per show, with a few of their details
+        let targets = router.targets(&episode());
+        assert_eq!(targets.len(), 1);
+        assert!(matches!(targets[0].thread, Some(Thread::Forum { .. })));
+        assert_eq!(
+            targets[0].fields,
+            Some(vec![Field::Runtime, Field::Rated, Field::Released])
+        );
+
+        // 4K movies are sent once, pinging the role that wants to know about them
+        let movie = payload(serde_json::json!({
+            "type": "movie",
+            "title": "Heat",
+            "librarySectionTitle": "4K Movies",
+        }));
+        let targets = router.targets(&movie);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].mentions.roles, ["123456789012345678"]);
+
+        let backup: Payload = serde_json::from_value(serde_json::json!({
+            "event": "admin.database.backup",
+            "Server": { "title": "Office" },
+        }))
+        .unwrap();
+        let targets = router.targets(&backup);
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].username.as_deref(), Some("Office"));
+        assert_eq!(
+            targets[0].avatar_url.as_deref(),
+            Some("https://example.com/plex.png")
+        );
+        assert_eq!(targets[0].mentions.users, ["234567890123456789"]);
+    }
+
+    fn forum() -> Route {
+        Route {
+            forum: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn no_thread_by_default() {
+        assert_eq!(Route::default().thread(&episode()), None);
+    }
+
+    #[test]
+    fn existing_thread_wins_over_forum() {
+        let route = Route {
+            thread_id: Some("42".into()),
+            ..forum()
+        };
+        assert_eq!(
+            route.thread(&episode()),
+            Some(Thread::Existing("42".into()))
+        );
+    }
+
+    #[test]
+    fn episodes_share_their_show_post() {
+        assert_eq!(
+            forum().thread(&episode()),
+            Some(Thread::Forum {
+                key: Some("2048".into()),
+                name: "Breaking Bad".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn anything_else_gets_its_own_post() {
+        let movie = payload(serde_json::json!({ "type": "movie", "title": "Heat" }));
+        assert_eq!(
+            forum().thread(&movie),
+            Some(Thread::Forum {
+                key: None,
+                name: "Heat".into(),
+            })
+        );
+
+        let track = payload(serde_json::json!({
+            "type": "track",
+            "title": "Airbag",
+            "ratingKey": "3102",
+            "parentRatingKey": "3101",
+            "grandparentRatingKey": "3100",
+            "parentTitle": "OK Computer",
+            "grandparentTitle": "Radiohead",
+        }));
+        assert_eq!(
+            forum().thread(&track),
+            Some(Thread::Forum {
+                key: None,
+                name: "Radiohead - OK Computer - Airbag".into(),
+            })
+        );
+
+        // Without metadata there's only the event to go by
+        let backup: Payload =
+            serde_json::from_value(serde_json::json!({ "event": "admin.database.backup" }))
+                .unwrap();
+        assert_eq!(
+            forum().thread(&backup),
+            Some(Thread::Forum {
+                key: None,
+                name: "admin.database.backup".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn post_names_fit_discord_limit() {
+        let movie = payload(serde_json::json!({ "type": "movie", "title": "長".repeat(150) }));
+        match forum().thread(&movie) {
+            Some(Thread::Forum { name, .. }) => assert_eq!(name.chars().count(), 100),
+            thread => panic!("Expected a forum post, got {thread:?}"),
+        }
+    }
+}
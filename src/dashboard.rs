@@ -0,0 +1,432 @@
+//! Keeps a single "now playing" message per plex server up to date, listing every session that is playing or paused.
+//!
+//! The message is posted the first time a playback event comes in from a server, and edited as sessions start, pause,
+//! resume and stop. It isn't remembered across restarts, a new one is posted instead.
+//!
+//! The dashboard runs as a task of its own, fed [Change]s over a channel, so a slow edit never holds up notifications.
+//! Changes that queue up while a message is being edited are applied together, and a message is only edited when what
+//! it shows has changed.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use color_eyre::Result;
+use discord_webhook::webhook::{
+    message_url, truncate, wait_url, Embed, Method, StatusError, WebhookExecutor, WebhookRequest,
+    DESCRIPTION_LIMIT,
+};
+use plex_webhook::models::{Event, Payload};
+use tokio::sync::mpsc::Receiver;
+use tokio::time::Instant;
+use tracing::{debug, error};
+
+use crate::render::{display_title, timestamp};
+
+/// Sessions that haven't been heard from in this long are assumed to have ended without a stop event
+const STALE_AFTER: Duration = Duration::from_secs(12 * 60 * 60);
+
+const COLOR_DASHBOARD: u32 = 0x1F8B4C;
+
+#[derive(Debug)]
+struct Session {
+    account: String,
+    title: String,
+    player: Option<String>,
+    paused: bool,
+    /// Milliseconds into the item, as of the last event
+    view_offset: Option<u64>,
+    /// Length of the item in milliseconds
+    duration: Option<u64>,
+    updated: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Board {
+    server: String,
+    message_id: Option<String>,
+    /// Description of the message as last published, to tell whether it needs editing
+    shown: Option<String>,
+    /// Sessions by player, since a player can only play one thing at a time
+    sessions: BTreeMap<String, Session>,
+}
+
+/// What a playback event changes on its server's board
+#[derive(Debug)]
+pub struct Change {
+    server_key: String,
+    server: String,
+    session_key: String,
+    /// The session as it is now, or [None] once it has stopped
+    session: Option<Session>,
+}
+
+impl Change {
+    /// The change this event makes, if it is a playback event
+    pub fn from_payload(payload: &Payload) -> Option<Self> {
+        let paused = match payload.event {
+            Event::MediaPlay | Event::MediaResume | Event::MediaScrobble => false,
+            Event::MediaPause => true,
+            Event::MediaStop => false,
+            _ => return None,
+        };
+
+        let server = payload.server.as_ref();
+        let server_key = server
+            .and_then(|s| s.uuid.clone().or_else(|| s.title.clone()))
+            .unwrap_or_default();
+
+        let metadata = payload.metadata.as_ref();
+        let player = payload.player.as_ref();
+        let account = payload.account_name().unwrap_or("Someone");
+        let session_key = player
+            .and_then(|p| p.uuid.clone())
+            .unwrap_or_else(|| account.to_string());
+
+        let session = (payload.event != Event::MediaStop).then(|| Session {
+            account: account.to_string(),
+            title: metadata.map_or_else(|| "something".to_string(), display_title),
+            player: player.and_then(|p| p.title.clone()),
+            paused,
+            view_offset: metadata.and_then(|m| m.view_offset),
+            duration: metadata.and_then(|m| m.duration),
+            updated: Instant::now(),
+        });
+
+        Some(Self {
+            server_key,
+            server: payload.server_name().unwrap_or("the server").to_string(),
+            session_key,
+            session,
+        })
+    }
+}
+
+/// Now playing messages for every server, all posted to the same webhook
+#[derive(Debug)]
+pub struct Dashboard {
+    url: String,
+    boards: HashMap<String, Board>,
+}
+
+impl Dashboard {
+    pub fn new(url: String) -> Self {
+        Self {
+            url,
+            boards: HashMap::new(),
+        }
+    }
+
+    /// Keep the boards up to date with the changes coming in, until every sender is gone
+    pub async fn run(mut self, mut changes: Receiver<Change>, client: WebhookExecutor) {
+        while let Some(change) = changes.recv().await {
+            let mut servers = vec![self.apply(change)];
+            while let Ok(change) = changes.try_recv() {
+                let server = self.apply(change);
+                if !servers.contains(&server) {
+                    servers.push(server);
+                }
+            }
+
+            for server in servers {
+                if let Some(board) = self.boards.get_mut(&server) {
+                    board.publish(&self.url, &client).await;
+                }
+            }
+        }
+    }
+
+    /// Update the sessions of the server the change is for, returning which server that is
+    fn apply(&mut self, change: Change) -> String {
+        let board = self
+            .boards
+            .entry(change.server_key.clone())
+            .or_insert_with(|| Board {
+                server: change.server,
+                ..Default::default()
+            });
+
+        match change.session {
+            Some(session) => {
+                board.sessions.insert(change.session_key, session);
+            }
+            None => {
+                board.sessions.remove(&change.session_key);
+            }
+        }
+        board
+            .sessions
+            .retain(|_, session| session.updated.elapsed() < STALE_AFTER);
+
+        change.server_key
+    }
+}
+
+impl Board {
+    /// Post this board's message, or edit it if it was already posted and shows something else
+    async fn publish(&mut self, url: &str, client: &WebhookExecutor) {
+        let embed = match self.render() {
+            Ok(embed) => embed,
+            Err(e) => {
+                error!(
+                    "Failed to render now playing message for {}: {e}",
+                    self.server
+                );
+                return;
+            }
+        };
+        if self.message_id.is_some() && self.shown == embed.description {
+            debug!("Now playing message for {} is unchanged", self.server);
+            return;
+        }
+
+        let description = embed.description.clone();
+        let request = WebhookRequest {
+            embeds: vec![embed],
+            ..Default::default()
+        };
+
+        // The dashboard is redrawn on every change, so failed updates aren't worth keeping as dead letters
+        let result = match &self.message_id {
+            Some(id) => {
+                request
+                    .deliver(client.clone(), Method::Patch, &message_url(url, id), &[])
+                    .await
+            }
+            None => {
+                request
+                    .deliver(client.clone(), Method::Post, &wait_url(url), &[])
+                    .await
+            }
+        };
+
+        match result {
+            Ok(Some(message)) => {
+                self.message_id = Some(message.id);
+                self.shown = description;
+            }
+            Ok(None) => debug!("Discord did not reply with the now playing message"),
+            Err(e) => {
+                error!(
+                    "Failed to update now playing message for {}: {e}",
+                    self.server
+                );
+                // The message was deleted, post a new one next time. Anything else may clear up, so keep editing it
+                if e.downcast_ref::<StatusError>()
+                    .is_some_and(StatusError::is_not_found)
+                {
+                    self.message_id = None;
+                }
+            }
+        }
+    }
+
+    fn render(&self) -> Result<Embed> {
+        let lines: Vec<String> = self.sessions.values().map(Session::render).collect();
+        let description = if lines.is_empty() {
+            "Nothing is playing".to_string()
+        } else {
+            fit_lines(&lines, DESCRIPTION_LIMIT)
+        };
+
+        Embed::builder()
+            .title(format!("Now playing on {}", self.server))
+            .color(COLOR_DASHBOARD)
+            .description(description)
+            .build()
+    }
+}
+
+/// Join as many whole lines as fit in `limit` characters, saying how many were left out if they don't all fit. The
+/// note about the rest counts against the limit too
+fn fit_lines(lines: &[String], limit: usize) -> String {
+    let all = lines.join("\n");
+    if all.chars().count() <= limit {
+        return all;
+    }
+
+    // Leave room for the note about the rest, at its longest
+    let reserve = format!("…and {} more", lines.len()).chars().count() + 1;
+
+    let mut text = String::new();
+    let mut length = 0;
+    let mut shown = 0;
+    for line in lines {
+        let line_length = line.chars().count() + 1;
+        if length + line_length + reserve > limit {
+            break;
+        }
+        text += line;
+        text.push('\n');
+        length += line_length;
+        shown += 1;
+    }
+
+    // With a limit too small for even the note, there's nothing better to do than cut it short
+    let rest = format!("…and {} more", lines.len() - shown);
+    text + &truncate(rest, limit - length)
+}
+
+impl Session {
+    fn render(&self) -> String {
+        let state = if self.paused { "⏸" } else { "▶" };
+        let mut line = format!("{state} **{}** {}", self.account, self.title);
+
+        if let Some(player) = &self.player {
+            line += &format!(" on {player}");
+        }
+        match (self.view_offset, self.duration) {
+            (Some(offset), Some(duration)) => {
+                line += &format!(" ({} / {})", timestamp(offset), timestamp(duration))
+            }
+            (None, Some(duration)) => line += &format!(" ({})", timestamp(duration)),
+            _ => {}
+        }
+
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use discord_webhook::mock::MockDiscord;
+
+    fn event(event: &str, player: &str) -> Payload {
+        serde_json::from_value(serde_json::json!({
+            "event": event,
+            "Account": { "title": "elan" },
+            "Server": { "title": "Office", "uuid": "office" },
+            "Player": { "title": player, "uuid": player },
+            "Metadata": { "type": "movie", "title": "Heat" },
+        }))
+        .unwrap()
+    }
+
+    /// Apply an event to the dashboard and publish its server's board, like [Dashboard::run] does
+    async fn update(dashboard: &mut Dashboard, payload: Payload, client: &WebhookExecutor) {
+        let server = dashboard.apply(Change::from_payload(&payload).unwrap());
+        let board = dashboard.boards.get_mut(&server).unwrap();
+        board.publish(&dashboard.url, client).await;
+    }
+
+    fn methods(discord: &MockDiscord) -> Vec<String> {
+        discord.requests().into_iter().map(|r| r.method).collect()
+    }
+
+    #[tokio::test]
+    async fn posts_again_only_once_the_message_is_gone() {
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let mut dashboard = Dashboard::new(discord.url("/api/webhooks/1/abc"));
+
+        update(&mut dashboard, event("media.play", "tv"), &client).await;
+        // Failures other than the message being gone leave it to be edited again
+        discord.reply(400, Vec::new(), r#"{"message": "Invalid Form Body"}"#);
+        update(&mut dashboard, event("media.pause", "tv"), &client).await;
+        update(&mut dashboard, event("media.pause", "tv"), &client).await;
+        discord.reply(
+            404,
+            Vec::new(),
+            r#"{"message": "Unknown Message", "code": 10008}"#,
+        );
+        update(&mut dashboard, event("media.resume", "tv"), &client).await;
+        update(&mut dashboard, event("media.stop", "tv"), &client).await;
+
+        assert_eq!(
+            methods(&discord),
+            ["POST", "PATCH", "PATCH", "PATCH", "POST"]
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_boards_are_left_alone() {
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let mut dashboard = Dashboard::new(discord.url("/api/webhooks/1/abc"));
+
+        update(&mut dashboard, event("media.play", "tv"), &client).await;
+        // Still playing, just as before
+        update(&mut dashboard, event("media.scrobble", "tv"), &client).await;
+        update(&mut dashboard, event("media.resume", "tv"), &client).await;
+        update(&mut dashboard, event("media.pause", "tv"), &client).await;
+
+        assert_eq!(methods(&discord), ["POST", "PATCH"]);
+    }
+
+    #[tokio::test]
+    async fn changes_that_queue_up_are_published_together() {
+        let discord = MockDiscord::start();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        for (name, player) in [
+            ("media.play", "tv"),
+            ("media.play", "phone"),
+            ("media.pause", "tv"),
+        ] {
+            tx.send(Change::from_payload(&event(name, player)).unwrap())
+                .await
+                .unwrap();
+        }
+        drop(tx);
+
+        Dashboard::new(discord.url("/api/webhooks/1/abc"))
+            .run(rx, WebhookExecutor::new())
+            .await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 1);
+        let body = requests[0].body_str();
+        assert!(body.contains("⏸ **elan** Heat on tv"), "{body}");
+        assert!(body.contains("▶ **elan** Heat on phone"), "{body}");
+    }
+
+    #[test]
+    fn only_playback_events_change_the_board() {
+        assert!(Change::from_payload(&event("media.rate", "tv")).is_none());
+        assert!(Change::from_payload(&event("media.stop", "tv"))
+            .unwrap()
+            .session
+            .is_none());
+    }
+
+    #[test]
+    fn session_list_fits_the_description() {
+        let lines: Vec<String> = (0..10).map(|n| format!("{n}").repeat(9)).collect();
+        assert_eq!(fit_lines(&lines[..2], 19), "000000000\n111111111");
+
+        // Whole lines are left out, with room kept to say how many
+        let fitted = fit_lines(&lines, 45);
+        assert_eq!(fitted, "000000000\n111111111\n222222222\n…and 7 more");
+        assert!(fitted.chars().count() <= 45);
+
+        // Even the note is cut down to fit
+        assert_eq!(fit_lines(&lines, 5), "…and…");
+    }
+
+    #[tokio::test]
+    async fn many_sessions_still_render() {
+        let mut board = Board {
+            server: "Office".into(),
+            ..Default::default()
+        };
+        for n in 0..200 {
+            board.sessions.insert(
+                format!("{n:03}"),
+                Session {
+                    account: format!("account {n}"),
+                    title: "A movie with a long title ".repeat(2),
+                    player: Some("Living Room TV".into()),
+                    paused: false,
+                    view_offset: Some(60_000),
+                    duration: Some(7_200_000),
+                    updated: Instant::now(),
+                },
+            );
+        }
+
+        let description = board.render().unwrap().description.unwrap();
+        assert!(description.chars().count() <= DESCRIPTION_LIMIT);
+        assert!(description.starts_with("▶ **account 0**"), "{description}");
+        assert!(description.ends_with(" more"), "{description}");
+    }
+}
@@ -1,4 +0,0 @@
-//! Provides facilities to construct basic rich messages and an API for posting those to a discord webhook
-
-/// Contains a webhook executor function and related state structures
-pub mod webhook;
@@ -1,45 +1,155 @@
 use color_eyre::Report;
 use tokio::join;
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::EnvFilter;
 
 use chrono::prelude::*;
 use futures::future::join_all;
 use std::io::Write;
 use std::path;
-use std::time::{Duration, SystemTime};
+use std::sync::Arc;
+use std::time::Duration;
 use std::{collections::HashMap, fs};
 
-mod discord;
-mod plex;
+mod auth;
+mod config;
+mod dashboard;
+mod outbox;
+mod render;
+mod sent;
+mod threads;
+mod throttle;
 
-use warp::Filter;
+use warp::{Filter, Rejection, Reply};
 const MAX_LENGTH: u64 = 1024 * 1024;
 
-use plex::webhook::PlexWebhookRequest;
+use plex_webhook::models::Event;
+use plex_webhook::webhook::{PlexWebhookRequest, WebhookError};
+use serde::Serialize;
 
 use clap::Parser;
 
-use crate::discord::webhook::{Embed, EmbedAuthor, EmbedFooter, WebhookExecutor};
+use discord_webhook::dead_letter::DeadLetterStore;
+use discord_webhook::retry::RetryPolicy;
+use discord_webhook::webhook::{
+    thread_url, wait_url, webhook_id, Attachment, Embed, EmbedAuthor, EmbedFooter, EmbedMedia,
+    StatusError, WebhookExecutor,
+};
+
+use crate::auth::Auth;
+use crate::config::{Aggregate, FileConfig, Rematch, Router, Target, Thread};
+use crate::dashboard::{Change, Dashboard};
+use crate::outbox::Outbox;
+use crate::render::{Notification, Sibling};
+use crate::sent::{Posted, Record, Sent};
+use crate::threads::Threads;
+use crate::throttle::Throttle;
 
 #[derive(Parser, Clone)]
 struct Config {
-    /// Webhook URL to post to, may be specified multiple times
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// TOML file defining destinations and routing rules, see the config module for the format
+    #[clap(short, long)]
+    config: Option<path::PathBuf>,
+
+    /// Webhook URL to post every event to, may be specified multiple times
     #[clap(short)]
     webhook_urls: Vec<String>,
 
     /// Port to listen on, default 8001
-    #[clap(default_value = "8001")]
-    port: u16,
+    port: Option<u16>,
+
+    /// Shared secret plex must send, as the path segment after /plex/ or the token query parameter
+    #[clap(long, env = "PLEX_WEBHOOK_SECRET")]
+    secret: Option<String>,
+
+    /// Only accept webhooks from this address or CIDR network, may be specified multiple times
+    #[clap(long = "allow")]
+    allowed_sources: Vec<String>,
 
     /// Save requests to a log folder
     #[clap(short)]
     save_requests: bool,
 
-    /// Throttle notifications for siblings to this many seconds between pings
-    #[clap(short, default_value = "0")]
-    throttle: u32,
+    /// Throttle notifications for siblings to this many seconds between pings, default 0 (no throttling)
+    #[clap(short)]
+    throttle: Option<u32>,
+
+    /// Send throttled siblings at most this many seconds after the first of them arrived, even if more keep coming (in
+    /// edit mode, stop adding to their message after this long), default no limit
+    #[clap(long)]
+    max_delay: Option<u32>,
+
+    /// Attempts to make at delivering each notification before giving up on it
+    #[clap(long, default_value = "5")]
+    retry_attempts: u32,
+
+    /// Milliseconds to wait before the first retry, doubling for each retry after
+    #[clap(long, default_value = "500")]
+    retry_backoff_ms: u64,
+
+    /// Upper bound in milliseconds on the wait between retries
+    #[clap(long, default_value = "60000")]
+    retry_max_backoff_ms: u64,
+
+    /// Fraction (0 to 1) of each retry delay to randomize
+    #[clap(long, default_value = "0.2")]
+    retry_jitter: f64,
+
+    /// Folder to save notifications that could not be delivered to
+    #[clap(long, default_value = "./dead_letters")]
+    dead_letter_dir: path::PathBuf,
+
+    /// Journal of requests that have been accepted but not yet sent, processed again on startup
+    #[clap(long, default_value = "./outbox.jsonl")]
+    outbox: path::PathBuf,
+
+    /// Where to remember the forum post made for each show
+    #[clap(long, default_value = "./threads.json")]
+    threads: path::PathBuf,
+
+    /// Where to remember the messages posted for newly added items, so they can be replaced if plex adds them again
+    #[clap(long, default_value = "./sent.json")]
+    sent: path::PathBuf,
+
+    /// How throttled siblings are combined, default hold
+    #[clap(long, arg_enum)]
+    aggregate: Option<Aggregate>,
+
+    /// What to do with the earlier notification for an item plex adds again, default keep
+    #[clap(long, arg_enum)]
+    rematch: Option<Rematch>,
+}
+
+impl Config {
+    fn port(&self) -> u16 {
+        self.port.unwrap_or(8001)
+    }
+
+    fn throttle(&self) -> u32 {
+        self.throttle.unwrap_or(0)
+    }
+
+    fn max_delay(&self) -> Option<Duration> {
+        self.max_delay.map(|secs| Duration::from_secs(secs.into()))
+    }
+
+    fn aggregate(&self) -> Aggregate {
+        self.aggregate.unwrap_or_default()
+    }
+
+    fn rematch(&self) -> Rematch {
+        self.rematch.unwrap_or_default()
+    }
+}
+
+#[derive(clap::Subcommand, Clone)]
+enum Command {
+    /// Attempt to deliver all saved undeliverable notifications again, then exit
+    Replay,
 }
 
 // Current thread scheduler to minimize overhead, and this should really all fit on one anyway
@@ -47,7 +157,31 @@ struct Config {
 async fn main() -> Result<(), Report> {
     setup()?;
 
-    let args = Config::parse();
+    let mut args = Config::parse();
+
+    let file_config = match &args.config {
+        Some(path) => FileConfig::load(path)?,
+        None => FileConfig::default(),
+    };
+
+    // Anything given on the command line takes precedence over the config file
+    args.port = args.port.or(file_config.port);
+    args.throttle = args.throttle.or(file_config.throttle);
+    args.max_delay = args.max_delay.or(file_config.max_delay);
+    args.aggregate = args.aggregate.or(file_config.aggregate);
+    args.rematch = args.rematch.or(file_config.rematch);
+    args.save_requests |= file_config.save_requests;
+    args.secret = args.secret.or_else(|| file_config.secret.clone());
+    if args.allowed_sources.is_empty() {
+        args.allowed_sources = file_config.allowed_sources.clone();
+    }
+
+    let auth = Arc::new(Auth::new(args.secret.clone(), &args.allowed_sources)?);
+    if auth.is_open() {
+        warn!("No secret or allowed sources configured, anyone who can reach this server can post notifications");
+    }
+
+    let router = Router::new(&file_config, &args.webhook_urls)?;
 
     // Save requests from plex just 'cause
     if args.save_requests {
@@ -64,22 +198,61 @@ async fn main() -> Result<(), Report> {
     // Buffer to transfer rate limited messages
     let (rate_limit_tx, mut rate_limit_rx) = tokio::sync::mpsc::channel(1024);
 
+    // Buffer to transfer playback changes to the dashboard, if there is one
+    let (dashboard_tx, dashboard_rx) = tokio::sync::mpsc::channel(1024);
+    let dashboard = router
+        .dashboard()
+        .map(|destination| Dashboard::new(destination.url.clone()));
+    let dashboard_tx = dashboard.is_some().then_some(dashboard_tx);
+
     // Internally this uses an Arc<Mutex<T>>, so cloning directly is cheap and safe
-    let discord_client = discord::webhook::WebhookExecutor::new();
+    let dead_letters = DeadLetterStore::new(&args.dead_letter_dir);
+    let discord_client = WebhookExecutor::new()
+        .with_retry_policy(RetryPolicy {
+            max_attempts: args.retry_attempts.max(1),
+            initial_backoff: Duration::from_millis(args.retry_backoff_ms),
+            max_backoff: Duration::from_millis(args.retry_max_backoff_ms),
+            jitter: args.retry_jitter,
+        })
+        .with_dead_letters(dead_letters.clone());
+
+    if let Some(Command::Replay) = args.command {
+        let (delivered, failed) = dead_letters.replay(discord_client).await?;
+        info!("Replayed dead letters: {delivered} delivered, {failed} still failing");
+        return Ok(());
+    }
+
+    // Anything left over from the last run is sent before new requests are accepted
+    let (outbox, pending) = Outbox::open(&args.outbox)?;
+    let threads = Threads::open(&args.threads)?;
+    let sent = Sent::open(&args.sent)?;
+    if !pending.is_empty() {
+        info!("Resuming {} unsent requests from the outbox", pending.len());
+    }
+    let resume_tx = tx.clone();
 
     // Accept and parse the webhook request and send it to a mpsc channel
     let api = warp::path("plex")
+        .and(auth::authorize(auth))
         .and(warp::post())
         // .and(log_body())
-        .and(warp::filters::multipart::form().max_length(MAX_LENGTH))
-        .and_then(plex::webhook::handle_webhook)
+        .and(plex_webhook::filter::webhook(MAX_LENGTH))
         // I feel like this clone should be rolled into the next closure but I'm not sure the syntax feature exists
-        .map(move |msg| (msg, tx.clone()))
-        .then(|arg: (PlexWebhookRequest, Sender<_>)| async {
-            let (msg, tx) = arg;
+        .map(move |msg| (msg, tx.clone(), outbox.clone()))
+        .then(|arg: (PlexWebhookRequest, Sender<_>, Outbox)| async {
+            let (msg, tx, outbox) = arg;
+
+            // Journal the request before acknowledging it, so it can't be lost once plex considers it delivered
+            let ticket = match outbox.accept(&msg) {
+                Ok(ticket) => ticket,
+                Err(e) => {
+                    error!("Failed to journal webhook request: {e}");
+                    return warp::http::StatusCode::INTERNAL_SERVER_ERROR;
+                }
+            };
 
             // Push the message onto a channel, with a check that the receiving end lives
-            match tx.send(msg).await {
+            match tx.send((ticket, msg)).await {
                 Ok(()) => warp::http::StatusCode::OK,
                 Err(e) => {
                     error!("Attempted to send webhook message to a dead channel with error {e}");
@@ -89,35 +262,73 @@ async fn main() -> Result<(), Report> {
                     warp::http::StatusCode::INTERNAL_SERVER_ERROR
                 }
             }
-        });
+        })
+        .recover(handle_rejection);
 
-    // Serve the API defined above
-    let server_future = warp::serve(api).run(([0, 0, 0, 0], args.port));
+    // Serve the API defined above, once the outbox has been drained into the queue
+    let server_future = async {
+        for item in pending {
+            if resume_tx.send(item).await.is_err() {
+                error!("Message queue closed while resuming the outbox");
+                return;
+            }
+        }
+        drop(resume_tx);
+
+        warp::serve(api).run(([0, 0, 0, 0], args.port())).await
+    };
 
     // Process received plex messages in one place, to allow combination and filtering of them
-    let messager_future = |args: Config, discord_client: WebhookExecutor| async move {
+    let messager_future = |args: Config,
+                           router: Router,
+                           discord_client: WebhookExecutor,
+                           threads: Threads,
+                           sent: Sent| async move {
         // Initialize a message template to clone for all further messages
-        let mut default_embed = Embed::default();
-        default_embed.author = Some(EmbedAuthor {
-            name: "derekw023/plex-discord-webhook".into(),
-            url: Some("https://github.com/derekw023/plex-discord-webhook".into()),
-            icon_url: Some("https://github.githubassets.com/favicons/favicon.svg".into()),
-            proxy_icon_url: None,
-        });
-        default_embed.footer = Some(EmbedFooter {
-            text: "Submit feature requests/bug reports on github".into(),
-            icon_url: Some("https://github.githubassets.com/favicons/favicon.svg".into()),
-            proxy_icon_url: None,
-        });
-        default_embed.url = Some("https://github.com/derekw023/plex-discord-webhook".into());
-
-        // Receive messages while there are publishers to the channel
-        while let Some(msg) = rx.recv().await {
+        let default_embed = Embed::builder()
+            .author(
+                EmbedAuthor::new("derekw023/plex-discord-webhook")
+                    .url("https://github.com/derekw023/plex-discord-webhook")
+                    .icon("https://github.githubassets.com/favicons/favicon.svg"),
+            )
+            .footer(
+                EmbedFooter::new("Submit feature requests/bug reports on github")
+                    .icon("https://github.githubassets.com/favicons/favicon.svg"),
+            )
+            .url("https://github.com/derekw023/plex-discord-webhook")
+            .build()
+            .expect("message template is within discord's limits");
+
+        // Siblings posted in edit mode, grouped the same way held back siblings are, and the messages showing each
+        // group. Once a group's window (or max delay) is up, the next sibling gets a message of its own
+        let mut edit_groups = Throttle::new(Duration::from_secs(args.throttle().into()))
+            .with_max_delay(args.max_delay());
+        let mut edit_posted: HashMap<(String, Vec<Target>), Vec<Posted>> = HashMap::new();
+
+        loop {
+            let deadline = edit_groups.next_deadline();
+
+            let (ticket, msg) = tokio::select! {
+                // Groups that are up are ended first, so a sibling coming in just after isn't added to them
+                biased;
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {
+                    for (key, _) in edit_groups.take_ready() {
+                        edit_posted.remove(&key);
+                    }
+                    continue;
+                }
+                // Receive messages while there are publishers to the channel
+                recvd = rx.recv() => match recvd {
+                    Some(recvd) => recvd,
+                    None => break,
+                },
+            };
+
             // Save message if directed to
             if args.save_requests {
                 // Come up with a name from a timestamp
                 let now = Utc::now();
-                let path = format!("./logs/{:?} - {now}.json", msg.payload.event);
+                let path = format!("./logs/{} - {now}.json", msg.payload.event);
                 let thumbpath = format!("./logs/{now}.jpeg");
                 let f = fs::OpenOptions::new()
                     .create_new(true)
@@ -125,226 +336,551 @@ async fn main() -> Result<(), Report> {
                     .open(path)
                     .unwrap();
 
-                if let Some(thumb) = msg.thumb {
+                if let Some(thumb) = &msg.thumb {
                     let mut thumbfile = fs::OpenOptions::new()
                         .create_new(true)
                         .write(true)
                         .open(thumbpath)
                         .unwrap();
 
-                    thumbfile.write_all(&thumb).unwrap();
+                    thumbfile.write_all(thumb).unwrap();
                 }
 
                 serde_json::to_writer_pretty(f, &msg.payload).unwrap();
             }
 
-            // Only handle library add events for now
-            // if let plex::models::Event::LibraryNew = msg.payload.event {
-            if let Some(metadata) = msg.payload.metadata {
-                debug!("{metadata:#?}");
+            // The dashboard sees every playback event, whether or not any route wants it
+            if let (Some(dashboard_tx), Some(change)) =
+                (&dashboard_tx, Change::from_payload(&msg.payload))
+            {
+                if dashboard_tx.send(change).await.is_err() {
+                    error!("Dashboard channel closed, now playing messages won't be updated");
+                }
+            }
+
+            // Work out where this should go before anything else, there's no point rendering it to go nowhere
+            let targets = router.targets(&msg.payload);
+            if targets.is_empty() {
+                debug!("No routes match {} event, dropping it", msg.payload.event);
+                ticket.done();
+                continue;
+            }
+
+            if let Some(mut rendered) = render::render(&msg.payload, &default_embed) {
+                debug!("{:#?}", msg.payload.metadata);
                 let mut embeds = Vec::new();
 
-                let mut em = default_embed.clone();
+                // Upload the poster plex sent along, if any, and show it as the embed's thumbnail
+                let attachment = msg.thumb.map(|data| Attachment {
+                    filename: "thumb.jpg".into(),
+                    content_type: "image/jpeg".into(),
+                    data,
+                });
+                if let Some(attachment) = &attachment {
+                    rendered.embed.thumbnail = Some(EmbedMedia::new(attachment.url()));
+                }
 
-                // Construct a message title from media metadata
-                let mut message_title = format!(
-                    "New {} added",
-                    metadata
-                        .media_type
-                        .clone()
-                        .unwrap_or_else(|| "media".to_string())
-                );
-                let mut message_description = String::new();
-
-                // Gracefully fall through, and fill in context based on what kind of content this is
-                if let Some(grandparent_title) = &metadata.grandparent_title {
-                    // has grandparent title, is a tv episode with associated season (parent) and show (this)
-                    message_title += &format!(": {grandparent_title}");
-                    if let Some(parent_title) = &metadata.parent_title {
-                        // Append season context
-                        message_title += &format!(" - {parent_title}");
-
-                        // Construct a description from media type, title and number
-                        if let Some(media_type) = metadata.media_type {
-                            message_description += &format!("{media_type} ");
-                        }
-
-                        // This will be the episode number for TV episodes
-                        if let Some(index) = metadata.index {
-                            message_description += &format!("{index}");
-                        }
-
-                        // Add episode title as description
-                        if let Some(title) = &metadata.title {
-                            message_description += &format!(": {title}");
-                        }
-                    }
-                } else if let Some(parent_title) = &metadata.parent_title {
-                    // no grandparent title, this item refers to a season of a show, or a show without seasons?
-                    message_title += &format!(": {parent_title}");
-
-                    if let Some(title) = &metadata.title {
-                        // Append season context
-                        message_title += &format!(" - {title}");
-
-                        // Construct a description from media type, title and number
-                        if let Some(media_type) = metadata.media_type {
-                            message_description += &format!("{media_type} ");
-                        }
-
-                        // This will be the episode number for TV episodes
-                        if let Some(index) = metadata.index {
-                            message_description += &format!("{index}");
-                        }
+                // Time throttle things if configured to, and if this should be throttled
+                let sibling_key = render::sibling_key(&msg.payload).filter(|_| args.throttle() > 0);
+                if let (Some(hash), Some(metadata)) = (sibling_key, &msg.payload.metadata) {
+                    // Siblings are only grouped together if they're headed to the same places
+                    let key = (hash, targets);
+                    let sibling = Sibling::new(rendered, metadata);
+
+                    if args.aggregate() == Aggregate::Hold {
+                        // The throttler takes over the ticket, it is done once the collapsed message is sent
+                        rate_limit_tx
+                            .send((key, sibling, attachment, ticket))
+                            .await
+                            .unwrap();
+                        continue;
                     }
-                } else if let Some(title) = &metadata.title {
-                    message_title += &format!(": {title}");
-                } else {
-                    error!("Metadata has no title... sending empty message");
-                }
-                // TODO: Find pictures
 
-                // Move into embed object
-                em.title = Some(message_title);
-                em.description = if message_description.is_empty() {
-                    None
-                } else {
-                    Some(message_description)
-                };
+                    // Add to the message of any sibling that came in recently enough, otherwise post a new one
+                    if edit_groups.items(&key).is_some() {
+                        edit_groups.push(key.clone(), sibling);
 
-                // Build a hash to uniquely ID this item's parents, if any
-                let mut hash = String::new();
-                if let Some(grandparent) = &metadata.grandparent_title {
-                    hash += grandparent;
-                }
-                if let Some(parent) = &metadata.parent_title {
-                    hash += parent;
-                }
+                        let notification = Notification {
+                            embeds: vec![render::collapse(
+                                edit_groups.items(&key).unwrap_or_default(),
+                            )],
+                            ..Default::default()
+                        };
+                        let posted = edit_posted.remove(&key).unwrap_or_default();
+                        let posted =
+                            edit_all(&discord_client, &key.1, &posted, &notification).await;
+                        edit_posted.insert(key, posted);
+                    } else {
+                        let notification = Notification {
+                            embeds: vec![sibling.rendered.clone()],
+                            files: attachment.into_iter().collect(),
+                            ..Default::default()
+                        };
+                        let posted =
+                            send_to_all(&discord_client, &threads, &key.1, &notification).await;
 
-                // Time throttle things if configured to, and if this should be throttled
-                if args.throttle > 0 && !hash.is_empty() {
-                    rate_limit_tx.send((hash, em)).await.unwrap();
+                        edit_posted.insert(key.clone(), posted);
+                        edit_groups.push(key, sibling);
+                    }
+
+                    ticket.done();
+                    continue;
                 } else {
                     // Add the embed to list to send
-                    embeds.push(em);
+                    embeds.push(rendered);
                 }
 
                 // Send something if there is something to send
                 if !embeds.is_empty() {
-                    // Wrap the embeds we made in a request object
-                    let request = discord::webhook::WebhookRequest::Embeds(embeds);
-
-                    // Execute the request against each webhook URL concurrently
-                    join_all(
-                        args.webhook_urls
-                            .iter()
-                            .map(|url| request.execute(discord_client.clone(), url)),
+                    // Wrap the embeds we made in a notification
+                    let notification = Notification {
+                        embeds,
+                        files: attachment.into_iter().collect(),
+                        ..Default::default()
+                    };
+
+                    // Newly added items are remembered, in case plex sends them again after re-matching them
+                    let items = msg
+                        .payload
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.rating_key.clone())
+                        .filter(|_| msg.payload.event == Event::LibraryNew)
+                        .into_iter()
+                        .collect();
+                    let record = Record {
+                        items,
+                        ..Default::default()
+                    };
+
+                    post_or_replace(
+                        &discord_client,
+                        &threads,
+                        &sent,
+                        args.rematch(),
+                        &targets,
+                        record,
+                        &notification,
                     )
                     .await;
                 }
             } else {
-                error!("Received a library new event without metadata, nothing to notify with");
+                warn!(
+                    "Nothing to notify for {} event, it may be missing metadata",
+                    msg.payload.event
+                );
             }
+
+            // Failed deliveries end up in the dead letter store, either way this request is finished
+            ticket.done();
             // }
         }
     };
 
-    // This should be refactored into the above future
-    //TODO: Do th^s
-    let rate_limiter_future = |args: Config, discord_client: WebhookExecutor| async move {
-        // Initialize a hashmap to manage a queue of sorts for rate limiting messages
-        let mut parents_map: HashMap<String, (tokio::time::Instant, Vec<Embed>)> = HashMap::new();
-        let mut oldest_ts = tokio::time::Instant::now();
-
-        let mut pending_requests = Vec::new();
+    // Sends siblings held back by the throttle once they stop coming in
+    let rate_limiter_future = |args: Config,
+                               discord_client: WebhookExecutor,
+                               threads: Threads,
+                               sent: Sent| async move {
+        let mut throttle = Throttle::new(Duration::from_secs(args.throttle().into()))
+            .with_max_delay(args.max_delay());
 
         loop {
-            let now = tokio::time::Instant::now();
-
-            // Schedule a wakeup on oldest_ts
-            let ratelimit_delay =
-                tokio::time::sleep_until(oldest_ts + Duration::from_secs(args.throttle.into()));
-            tokio::pin!(ratelimit_delay);
+            let deadline = throttle.next_deadline();
 
-            // wake up on the sooner of: something comes in on the channel or timer expires
+            // wake up on the sooner of: something comes in on the channel or the oldest group is due
             tokio::select! {
                 recvd = rate_limit_rx.recv() => {
-                    if let Some((hash, em)) = recvd {
-                        if let Some(val) = parents_map.get_mut(&hash) {
-                            let (ts, embeds) = val;
-                            *ts = now;
-                            embeds.push(em);
-                        } else {
-                            // Initialize the item to just this pending message
-                            parents_map.insert(hash, (now, vec![em]));
-                        }
-                    } else {
+                    match recvd {
+                        Some((key, sibling, attachment, ticket)) => throttle.push(key, (sibling, attachment, ticket)),
                         // End execution of this future if no senders exist
-                        return;
+                        None => return,
                     }
                 },
                 // disable if no pending messages
-                _ = &mut ratelimit_delay, if !parents_map.is_empty()=> {
-                    // Iterate all elements in the map and send ones that are old enough
-                    parents_map = parents_map
-                    .into_iter()
-                    .filter_map(|arg| {
-                        let (key, (ts, ems)) = arg;
-
-                        // Send if old enough
-                        if now.duration_since(ts)
-                            >= std::time::Duration::from_secs(args.throttle.into())
-                        {
-                            // Basically, collapse all existing embeds into one and pop from hashmap
-                            // Stack descriptions up with newlines in between but just copy the first embed for all other fields
-                            let desc = ems.iter().fold(String::new(), |mut d, l| {
-                                d += l.description.as_ref().unwrap();
-                                d += "\n";
-                                d
-                            });
-
-                            let mut whole_embed = ems[0].clone();
-                            whole_embed.description = Some(desc);
-
-                            // Push the request to execute onto a queue of requests
-                            let request = discord::webhook::WebhookRequest::Embeds(vec![whole_embed]);
-                            pending_requests.push(request);
-
-                            // Finally, remove from the hashmap
-                            None
-                        } else {
-                            // Update oldest ts
-                            oldest_ts = now.min(ts);
-                            // else, keep for next pass
-                            Some((key, (ts, ems)))
-                        }
-                    })
-                    .collect();
-                }
+                _ = tokio::time::sleep_until(deadline.unwrap_or_else(tokio::time::Instant::now)), if deadline.is_some() => {}
             };
 
             // Send everything that is ready to send
-            while let Some(req) = pending_requests.pop() {
-                // Execute the request against each webhook URL concurrently
-                join_all(
-                    args.webhook_urls
-                        .iter()
-                        .map(|url| req.execute(discord_client.clone(), url)),
+            for ((_, targets), items) in throttle.take_ready() {
+                // Only the first attachment in each group is kept, since only the first embed's fields are used
+                let mut siblings = Vec::new();
+                let mut tickets = Vec::new();
+                let mut attachment = None;
+                for (sibling, item_attachment, ticket) in items {
+                    siblings.push(sibling);
+                    tickets.push(ticket);
+                    attachment = attachment.or(item_attachment);
+                }
+
+                let notification = Notification {
+                    embeds: vec![render::collapse(&siblings)],
+                    files: attachment.into_iter().collect(),
+                    ..Default::default()
+                };
+
+                // The group is remembered with its items, so it can be replaced or redrawn if any are added again
+                let mut items: Vec<String> = Vec::new();
+                for key in siblings.iter().filter_map(|s| s.rating_key.clone()) {
+                    if !items.contains(&key) {
+                        items.push(key);
+                    }
+                }
+                let record = Record {
+                    items,
+                    posted: Vec::new(),
+                    siblings,
+                };
+
+                post_or_replace(
+                    &discord_client,
+                    &threads,
+                    &sent,
+                    args.rematch(),
+                    &targets,
+                    record,
+                    &notification,
                 )
                 .await;
+
+                for ticket in tickets {
+                    ticket.done();
+                }
             }
         }
     };
 
     info!("Starting up plex webhook relay");
     join!(
-        messager_future(args.clone(), discord_client.clone()),
+        messager_future(
+            args.clone(),
+            router,
+            discord_client.clone(),
+            threads.clone(),
+            sent.clone()
+        ),
         server_future,
-        rate_limiter_future(args.clone(), discord_client.clone())
+        rate_limiter_future(args.clone(), discord_client.clone(), threads, sent),
+        async {
+            if let Some(dashboard) = dashboard {
+                dashboard.run(dashboard_rx, discord_client.clone()).await;
+            }
+        }
     );
     Ok(())
 }
 
+/// JSON body of error replies, so misconfigured plex servers show what went wrong in their webhook logs
+#[derive(Serialize)]
+struct ErrorReply {
+    error: &'static str,
+    message: String,
+}
+
+/// Turn rejections we know the meaning of into proper status codes, leaving the rest to warp's defaults
+async fn handle_rejection(err: Rejection) -> Result<impl Reply, Rejection> {
+    use warp::http::StatusCode;
+
+    let (status, error, message) = if let Some(e) = err.find::<WebhookError>() {
+        (
+            StatusCode::from_u16(e.status()).unwrap_or(StatusCode::BAD_REQUEST),
+            e.kind(),
+            e.message(),
+        )
+    } else if err.find::<auth::Unauthorized>().is_some() {
+        (
+            StatusCode::UNAUTHORIZED,
+            "unauthorized",
+            "Missing or incorrect secret".to_string(),
+        )
+    } else if err.find::<auth::Forbidden>().is_some() {
+        (
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "Source address not allowed".to_string(),
+        )
+    } else {
+        return Err(err);
+    };
+
+    warn!("Rejecting webhook request with {status}: {message}");
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorReply { error, message }),
+        status,
+    ))
+}
+
+/// Split a notification into pieces discord will accept, and execute them in order against each target, with all
+/// targets concurrently. Files are uploaded with the piece carrying the first embeds, which is the only one that can refer to
+/// them.
+///
+/// Returns the first message posted to each target, for targets that were posted to
+async fn send_to_all(
+    client: &WebhookExecutor,
+    threads: &Threads,
+    targets: &[Target],
+    notification: &Notification,
+) -> Vec<Posted> {
+    join_all(
+        targets
+            .iter()
+            .map(|target| send_to_target(client, threads, target, notification)),
+    )
+    .await
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+/// Execute the pieces of a notification against a single target, in its thread if it has one. Returns the first message
+/// posted, if it could be
+async fn send_to_target(
+    client: &WebhookExecutor,
+    threads: &Threads,
+    target: &Target,
+    notification: &Notification,
+) -> Option<Posted> {
+    // Targets may add content of their own, so split each target's request separately
+    let mut request = target.apply(notification);
+    let mut thread_id = None;
+
+    // A new forum post is made by the first piece, and discord's reply says where it went
+    let mut new_post = None;
+    match &target.thread {
+        Some(Thread::Existing(id)) => thread_id = Some(id.clone()),
+        Some(Thread::Forum { key, name }) => {
+            match key.as_deref().and_then(|key| threads.get(&target.url, key)) {
+                Some(id) => thread_id = Some(id),
+                None => {
+                    request.metadata.thread_name = Some(name.clone());
+                    new_post = Some(key.as_deref());
+                }
+            }
+        }
+        None => {}
+    }
+    let mut url = match &thread_id {
+        Some(id) => thread_url(&target.url, id),
+        None => target.url.clone(),
+    };
+
+    let pieces = request.split();
+    // Long content comes before the embeds, so they may not be in the first piece
+    let with_files = pieces
+        .iter()
+        .position(|piece| !piece.embeds.is_empty())
+        .unwrap_or(0);
+
+    let mut posted = None;
+    for (n, mut request) in pieces.into_iter().enumerate() {
+        if n > 0 {
+            request.metadata.thread_name = None;
+        }
+        let files = if n == with_files {
+            notification.files.as_slice()
+        } else {
+            &[]
+        };
+
+        // Wait for the first message, so it can be edited later
+        let result = if n == 0 {
+            request
+                .execute(client.clone(), &wait_url(&url), files)
+                .await
+        } else {
+            request.execute(client.clone(), &url, files).await
+        };
+
+        match result {
+            Ok(Some(message)) if n == 0 => {
+                if let Some(key) = new_post {
+                    // The new post's thread has the same ID as its channel
+                    url = thread_url(&target.url, &message.channel_id);
+                    if let Some(key) = key {
+                        threads.remember(&target.url, key, &message.channel_id);
+                    }
+                    thread_id = Some(message.channel_id);
+                }
+                posted = Some(Posted {
+                    webhook_id: webhook_id(&target.url).to_string(),
+                    thread_id: thread_id.clone(),
+                    message_id: message.id,
+                });
+            }
+            Ok(None) if n == 0 && new_post.is_some() => {
+                error!("Discord did not say where the new forum post went, dropping the rest of the message");
+                return None;
+            }
+            Ok(_) => {}
+            Err(e) if n == 0 && new_post.is_some() => {
+                error!("Failed to make forum post, dropping the rest of the message: {e}");
+                return None;
+            }
+            Err(e) => error!("Failed to deliver discord notification: {e}"),
+        }
+    }
+
+    posted
+}
+
+/// Post a notification for newly added items, in place of whatever was posted when they were added before. Depending
+/// on `rematch`, messages that showed exactly these items are edited to show the notification instead, or deleted and
+/// the notification posted again. Messages that showed these items among others are redrawn without them.
+///
+/// The messages now showing the items are remembered in `sent`, with the record of the items
+async fn post_or_replace(
+    client: &WebhookExecutor,
+    threads: &Threads,
+    sent: &Sent,
+    rematch: Rematch,
+    targets: &[Target],
+    mut record: Record,
+    notification: &Notification,
+) {
+    if rematch == Rematch::Keep {
+        send_to_all(client, threads, targets, notification).await;
+        return;
+    }
+
+    // Taking an item forgets the rest of its group too, so each earlier record only turns up once
+    let mut replaced = Vec::new();
+    for item in record.items.clone() {
+        let Some(previous) = sent.take(&item) else {
+            continue;
+        };
+        if previous.shows(&record.items) {
+            replaced = previous.posted;
+        } else {
+            withdraw(client, sent, targets, previous, &record.items).await;
+        }
+    }
+
+    if rematch == Rematch::Edit && !replaced.is_empty() {
+        info!("Editing the earlier notification for re-added items");
+        record.posted = edit_all(client, targets, &replaced, notification).await;
+    }
+    // Post again if there was nothing to edit, or everything to edit has since been deleted
+    if record.posted.is_empty() {
+        if rematch == Rematch::Delete && !replaced.is_empty() {
+            info!("Deleting the earlier notification for re-added items");
+            delete_all(client, targets, &replaced).await;
+        }
+        record.posted = send_to_all(client, threads, targets, notification).await;
+    }
+
+    sent.remember(record);
+}
+
+/// Take re-added items out of the messages posted for a group, redrawing them to show the rest of the group, or
+/// deleting them if nothing is left
+async fn withdraw(
+    client: &WebhookExecutor,
+    sent: &Sent,
+    targets: &[Target],
+    mut previous: Record,
+    items: &[String],
+) {
+    previous.items.retain(|item| !items.contains(item));
+    previous
+        .siblings
+        .retain(|s| s.rating_key.as_ref().is_none_or(|key| !items.contains(key)));
+
+    if previous.items.is_empty() || previous.siblings.is_empty() {
+        info!("Deleting the earlier notification for re-added items");
+        delete_all(client, targets, &previous.posted).await;
+        return;
+    }
+
+    info!("Redrawing the earlier notification for a group without its re-added items");
+    let notification = Notification {
+        embeds: vec![render::collapse(&previous.siblings)],
+        ..Default::default()
+    };
+    previous.posted = edit_all(client, targets, &previous.posted, &notification).await;
+    sent.remember(previous);
+}
+
+/// Edit messages posted earlier to show this notification instead, dressed up for the target each was posted to, with
+/// any files replacing those attached. Only the first piece of it fits in a message, anything more is left out.
+///
+/// Returns the messages that are still there, leaving out any that turned out to have been deleted
+async fn edit_all(
+    client: &WebhookExecutor,
+    targets: &[Target],
+    posted: &[Posted],
+    notification: &Notification,
+) -> Vec<Posted> {
+    let edits = posted.iter().map(|p| async move {
+        let Some(target) = located(p, targets) else {
+            return true;
+        };
+
+        let mut pieces = target.apply(notification).split().into_iter();
+        let Some(piece) = pieces.next() else {
+            return true;
+        };
+        if pieces.next().is_some() {
+            warn!("Edited message is too long for one message, leaving out the rest");
+        }
+
+        let url = p.url(&target.url);
+        match piece
+            .edit(client.clone(), &url, &p.message_id, &notification.files)
+            .await
+        {
+            Ok(_) => true,
+            Err(e)
+                if e.downcast_ref::<StatusError>()
+                    .is_some_and(StatusError::is_not_found) =>
+            {
+                warn!(
+                    "Discord notification {} was deleted, so it can't be edited",
+                    p.message_id
+                );
+                false
+            }
+            Err(e) => {
+                error!("Failed to edit discord notification: {e}");
+                true
+            }
+        }
+    });
+
+    let still_there = join_all(edits).await;
+    posted
+        .iter()
+        .zip(still_there)
+        .filter(|(_, there)| *there)
+        .map(|(p, _)| p.clone())
+        .collect()
+}
+
+/// Delete messages posted earlier
+async fn delete_all(client: &WebhookExecutor, targets: &[Target], posted: &[Posted]) {
+    let deletes = posted.iter().filter_map(|p| {
+        let target = located(p, targets)?;
+        let url = p.url(&target.url);
+        Some(async move { client.delete_message(&url, &p.message_id).await })
+    });
+
+    for e in join_all(deletes).await.into_iter().filter_map(Result::err) {
+        error!("Failed to delete discord notification: {e}");
+    }
+}
+
+/// The target a message was posted to, out of these. Messages on webhooks that aren't targeted any more can't be
+/// reached, since only the targets know the webhooks' tokens
+fn located<'a>(posted: &Posted, targets: &'a [Target]) -> Option<&'a Target> {
+    let target = posted.target(targets);
+    if target.is_none() {
+        warn!(
+            "Nothing is routed to the webhook message {} was posted through any more, leaving it as it is",
+            posted.message_id
+        );
+    }
+    target
+}
+
 fn setup() -> Result<(), Report> {
     if std::env::var("RUST_LIB_BACKTRACE").is_err() {
         std::env::set_var("RUST_LIB_BACKTRACE", "1")
@@ -355,7 +891,7 @@ fn setup() -> Result<(), Report> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var(
             "RUST_LOG",
-            "plex_discord_webhook=info,plex_discord_webhook::plex=info,plex_discord_webhook::discord=info",
+            "plex_discord_webhook=info,plex_webhook=info,discord_webhook=info",
         )
     }
     tracing_subscriber::fmt::fmt()
@@ -364,3 +900,376 @@ fn setup() -> Result<(), Report> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use discord_webhook::mock::MockDiscord;
+    use discord_webhook::webhook::AllowedMention;
+
+    use crate::render::{Field, Rendered};
+
+    fn target(url: String, thread: Option<Thread>) -> Target {
+        Target {
+            url,
+            username: None,
+            avatar_url: None,
+            mentions: AllowedMention::default(),
+            thread,
+            fields: None,
+        }
+    }
+
+    fn poster() -> Attachment {
+        Attachment {
+            filename: "poster.jpg".into(),
+            content_type: "image/jpeg".into(),
+            data: b"jpeg".to_vec(),
+        }
+    }
+
+    /// Content long enough to take three messages, followed by an embed showing the poster
+    fn long_notification() -> Notification {
+        Notification {
+            content: Some("line\n".repeat(1000)),
+            embeds: vec![Embed::builder()
+                .title("Heat")
+                .thumbnail(poster().url())
+                .build()
+                .unwrap()
+                .into()],
+            files: vec![poster()],
+        }
+    }
+
+    #[tokio::test]
+    async fn files_are_uploaded_with_the_embeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads = Threads::open(&dir.path().join("threads.json")).unwrap();
+        let discord = MockDiscord::start();
+        let target = target(discord.url("/api/webhooks/1/abc"), None);
+
+        let posted = send_to_target(
+            &WebhookExecutor::new(),
+            &threads,
+            &target,
+            &long_notification(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(posted.message_id, "1");
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        for request in &requests[..2] {
+            assert_eq!(request.content_type.as_deref(), Some("application/json"));
+            assert!(!request.body_str().contains("Heat"));
+        }
+
+        let body = requests[2].body_str();
+        assert!(requests[2]
+            .content_type
+            .as_deref()
+            .unwrap()
+            .starts_with("multipart/form-data"));
+        assert!(body.contains(r#""title":"Heat""#), "{body}");
+        assert!(body.contains(r#"filename="poster.jpg""#), "{body}");
+    }
+
+    #[tokio::test]
+    async fn long_forum_posts_continue_in_the_post() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads = Threads::open(&dir.path().join("threads.json")).unwrap();
+        let discord = MockDiscord::start();
+        let url = discord.url("/api/webhooks/1/abc");
+        let target = target(
+            url.clone(),
+            Some(Thread::Forum {
+                key: Some("2048".into()),
+                name: "Breaking Bad".into(),
+            }),
+        );
+
+        let posted = send_to_target(
+            &WebhookExecutor::new(),
+            &threads,
+            &target,
+            &Notification {
+                files: Vec::new(),
+                ..long_notification()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(posted.webhook_id, "1");
+        assert_eq!(posted.thread_id.as_deref(), Some("c1"));
+        assert_eq!(posted.message_id, "1");
+        assert_eq!(threads.get(&url, "2048").as_deref(), Some("c1"));
+
+        // The first piece makes the post, the rest go in it
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].uri, "/api/webhooks/1/abc?wait=true");
+        assert!(requests[0]
+            .body_str()
+            .contains(r#""thread_name":"Breaking Bad""#));
+        for request in &requests[1..] {
+            assert_eq!(request.uri, "/api/webhooks/1/abc?thread_id=c1");
+            assert!(!request.body_str().contains("thread_name"));
+        }
+
+        // Later requests for the show go straight into the post
+        send_to_target(
+            &WebhookExecutor::new(),
+            &threads,
+            &target,
+            &Notification {
+                content: Some("Another".into()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let requests = discord.requests();
+        assert_eq!(
+            requests[3].uri,
+            "/api/webhooks/1/abc?thread_id=c1&wait=true"
+        );
+        assert!(!requests[3].body_str().contains("thread_name"));
+    }
+
+    #[tokio::test]
+    async fn edits_are_dressed_up_for_their_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads = Threads::open(&dir.path().join("threads.json")).unwrap();
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let target = Target {
+            username: Some("Plex".into()),
+            mentions: AllowedMention::only(vec!["55".into()], Vec::new()),
+            fields: Some(vec![Field::Runtime]),
+            ..target(
+                discord.url("/api/webhooks/1/abc"),
+                Some(Thread::Existing("42".into())),
+            )
+        };
+
+        let first = send_to_target(&client, &threads, &target, &long_notification())
+            .await
+            .unwrap();
+        // Posted through a webhook that's no longer routed to, so it can't be reached
+        let gone = Posted {
+            webhook_id: "2".into(),
+            thread_id: None,
+            message_id: "9".into(),
+        };
+
+        let mut rendered: Rendered = Embed::builder()
+            .title("Heat (1995)")
+            .thumbnail(poster().url())
+            .build()
+            .unwrap()
+            .into();
+        rendered.add_detail(Field::Director, "Michael Mann", true);
+        rendered.add_detail(Field::Runtime, "2 h 50 min", true);
+        let notification = Notification {
+            embeds: vec![rendered],
+            files: vec![poster()],
+            ..Default::default()
+        };
+        let still_there = edit_all(
+            &client,
+            std::slice::from_ref(&target),
+            &[first.clone(), gone.clone()],
+            &notification,
+        )
+        .await;
+        assert_eq!(still_there, [first, gone]);
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 4);
+        let edit = &requests[3];
+        assert_eq!(edit.method, "PATCH");
+        assert_eq!(edit.uri, "/api/webhooks/1/abc/messages/1?thread_id=42");
+
+        let body = edit.body_str();
+        assert!(body.contains(r#""username":"Plex""#), "{body}");
+        assert!(body.contains(r#""content":"<@&55>""#), "{body}");
+        assert!(body.contains(r#""roles":["55"]"#), "{body}");
+        assert!(
+            body.contains("2 h 50 min") && !body.contains("Director"),
+            "{body}"
+        );
+        // The poster is uploaded again, replacing the one already attached
+        assert!(body.contains(r#"filename="poster.jpg""#), "{body}");
+        assert!(body.contains(r#""attachments":[{"#), "{body}");
+    }
+
+    fn episode(index: u64) -> Sibling {
+        let metadata = serde_json::from_value(serde_json::json!({
+            "type": "episode",
+            "ratingKey": format!("e{index}"),
+            "title": format!("Episode {index}"),
+            "parentIndex": 1,
+            "index": index,
+            "grandparentTitle": "Breaking Bad",
+        }))
+        .unwrap();
+        Sibling::new(Embed::default().into(), &metadata)
+    }
+
+    fn heat() -> Notification {
+        Notification {
+            content: Some("Heat".into()),
+            ..Default::default()
+        }
+    }
+
+    fn items(keys: &[&str]) -> Record {
+        Record {
+            items: keys.iter().map(|key| key.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn re_added_items_replace_their_notification() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads = Threads::open(&dir.path().join("threads.json")).unwrap();
+        let sent = Sent::open(&dir.path().join("sent.json")).unwrap();
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let targets = vec![target(discord.url("/api/webhooks/1/abc"), None)];
+
+        // Posted the first time, deleted and posted again the second
+        for _ in 0..2 {
+            post_or_replace(
+                &client,
+                &threads,
+                &sent,
+                Rematch::Delete,
+                &targets,
+                items(&["1936"]),
+                &heat(),
+            )
+            .await;
+        }
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[1].method, "DELETE");
+        assert_eq!(requests[1].uri, "/api/webhooks/1/abc/messages/1");
+        assert_eq!(requests[2].method, "POST");
+
+        // Edited in place, after a restart
+        let sent = Sent::open(&dir.path().join("sent.json")).unwrap();
+        post_or_replace(
+            &client,
+            &threads,
+            &sent,
+            Rematch::Edit,
+            &targets,
+            items(&["1936"]),
+            &heat(),
+        )
+        .await;
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 4);
+        assert_eq!(requests[3].method, "PATCH");
+        assert_eq!(requests[3].uri, "/api/webhooks/1/abc/messages/2");
+
+        // Unless it has been deleted since, then it's posted again
+        discord.reply(404, Vec::new(), r#"{"message": "Unknown Message"}"#);
+        post_or_replace(
+            &client,
+            &threads,
+            &sent,
+            Rematch::Edit,
+            &targets,
+            items(&["1936"]),
+            &heat(),
+        )
+        .await;
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 6);
+        assert_eq!(requests[4].method, "PATCH");
+        assert_eq!(requests[5].method, "POST");
+        assert_eq!(sent.take("1936").unwrap().posted[0].message_id, "4");
+    }
+
+    #[tokio::test]
+    async fn re_added_items_are_taken_out_of_their_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let threads = Threads::open(&dir.path().join("threads.json")).unwrap();
+        let sent = Sent::open(&dir.path().join("sent.json")).unwrap();
+        let discord = MockDiscord::start();
+        let client = WebhookExecutor::new();
+        let targets = vec![target(discord.url("/api/webhooks/1/abc"), None)];
+
+        let siblings = vec![episode(1), episode(2), episode(3)];
+        let group = Notification {
+            embeds: vec![render::collapse(&siblings)],
+            ..Default::default()
+        };
+        let record = Record {
+            siblings,
+            ..items(&["e1", "e2", "e3"])
+        };
+        post_or_replace(
+            &client,
+            &threads,
+            &sent,
+            Rematch::Edit,
+            &targets,
+            record,
+            &group,
+        )
+        .await;
+
+        // The episode gets a message of its own, and the group's is redrawn without it
+        post_or_replace(
+            &client,
+            &threads,
+            &sent,
+            Rematch::Edit,
+            &targets,
+            items(&["e2"]),
+            &heat(),
+        )
+        .await;
+
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].body_str().contains("S01E01–E03 (3 episodes)"));
+        assert_eq!(requests[1].method, "PATCH");
+        assert_eq!(requests[1].uri, "/api/webhooks/1/abc/messages/1");
+        let body = requests[1].body_str();
+        assert!(body.contains("S01E01, E03 (2 episodes)"), "{body}");
+        assert!(!body.contains("Episode 2"), "{body}");
+        assert_eq!(requests[2].method, "POST");
+
+        let rest = sent.take("e3").unwrap();
+        assert!(rest.shows(&["e1".into(), "e3".into()]));
+        assert_eq!(rest.posted[0].message_id, "1");
+        assert_eq!(sent.take("e2").unwrap().posted[0].message_id, "3");
+        sent.remember(rest);
+
+        // Once nothing is left of the group, its message goes
+        post_or_replace(
+            &client,
+            &threads,
+            &sent,
+            Rematch::Edit,
+            &targets,
+            items(&["e1", "e3", "e4"]),
+            &heat(),
+        )
+        .await;
+        let requests = discord.requests();
+        assert_eq!(requests.len(), 5);
+        assert_eq!(requests[3].method, "DELETE");
+        assert_eq!(requests[3].uri, "/api/webhooks/1/abc/messages/1");
+        assert_eq!(requests[4].method, "POST");
+    }
+}
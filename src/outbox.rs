@@ -0,0 +1,336 @@
+//! An append-only journal of accepted plex requests, so that notifications still waiting to be sent (including ones
+//! held back by the throttle) survive a restart.
+//!
+//! Every request is journaled when it is accepted, and marked done once everything derived from it has been handed to
+//! discord. The journal is compacted down to the requests that were never marked done on startup, and every so often
+//! while running, so it doesn't grow without bound. On startup the requests left are processed again before any new
+//! requests are accepted.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use color_eyre::{eyre::WrapErr, Result};
+use plex_webhook::models::Payload;
+use plex_webhook::webhook::PlexWebhookRequest;
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+/// Compact the journal after this many requests are marked done
+const COMPACT_EVERY: usize = 256;
+
+/// A single line in the journal. Generic so that requests can be written from a reference and read back owned
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "lowercase")]
+enum Entry<R> {
+    Accepted { id: u64, request: R },
+    Done { id: u64 },
+}
+
+/// A request as it is journaled, with the thumbnail base64 encoded rather than as an array of numbers
+#[derive(Debug, Serialize, Deserialize)]
+struct Stored<P> {
+    payload: P,
+    thumb: Option<String>,
+}
+
+#[derive(Debug)]
+struct Journal {
+    path: PathBuf,
+    file: fs::File,
+    next_id: u64,
+    /// Requests marked done since the journal was last compacted
+    done_since_compact: usize,
+}
+
+/// Handle to the journal, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    journal: Arc<Mutex<Journal>>,
+}
+
+/// Tracks a journaled request through processing. Clone it into everything derived from the request, and call
+/// [Ticket::done] on each clone once it has been sent; the request is marked done when the last clone is.
+///
+/// Clones that are dropped without calling [Ticket::done] leave the request pending, so it will be processed again on
+/// the next startup.
+#[derive(Debug, Clone)]
+pub struct Ticket(Arc<TicketInner>);
+
+#[derive(Debug)]
+struct TicketInner {
+    id: u64,
+    outbox: Outbox,
+}
+
+impl Outbox {
+    /// Open (or create) the journal at this path, returning it along with every request that was accepted but never
+    /// finished, oldest first
+    pub fn open(path: &Path) -> Result<(Self, Vec<(Ticket, PlexWebhookRequest)>)> {
+        let lines = compact(path)?;
+
+        let mut pending = Vec::new();
+        for line in &lines {
+            match serde_json::from_str::<Entry<Stored<Payload>>>(line) {
+                Ok(Entry::Accepted { id, request }) => pending.push((id, request.into_request()?)),
+                Ok(Entry::Done { .. }) => {}
+                Err(e) => warn!("Skipping unreadable outbox entry: {e}"),
+            }
+        }
+
+        let file = fs::OpenOptions::new().append(true).open(path)?;
+        let outbox = Self {
+            journal: Arc::new(Mutex::new(Journal {
+                path: path.to_path_buf(),
+                file,
+                next_id: pending.iter().map(|(id, _)| id + 1).max().unwrap_or(0),
+                done_since_compact: 0,
+            })),
+        };
+
+        let pending = pending
+            .into_iter()
+            .map(|(id, request)| (outbox.ticket(id), request))
+            .collect();
+
+        Ok((outbox, pending))
+    }
+
+    /// Journal a newly accepted request. Only returns once the entry is on disk
+    pub fn accept(&self, request: &PlexWebhookRequest) -> Result<Ticket> {
+        let mut journal = self.journal.lock().unwrap();
+        let id = journal.next_id;
+
+        let request = Stored {
+            payload: &request.payload,
+            thumb: request.thumb.as_ref().map(|t| BASE64.encode(t)),
+        };
+        write_entry(&mut journal.file, &Entry::Accepted { id, request })?;
+        journal.file.sync_data()?;
+        journal.next_id += 1;
+        drop(journal);
+
+        Ok(self.ticket(id))
+    }
+
+    fn ticket(&self, id: u64) -> Ticket {
+        Ticket(Arc::new(TicketInner {
+            id,
+            outbox: self.clone(),
+        }))
+    }
+
+    fn complete(&self, id: u64) {
+        let mut journal = self.journal.lock().unwrap();
+        if let Err(e) = write_entry(&mut journal.file, &Entry::<()>::Done { id }) {
+            error!("Failed to mark outbox entry {id} done, it will be sent again on restart: {e}");
+            return;
+        }
+
+        journal.done_since_compact += 1;
+        if journal.done_since_compact >= COMPACT_EVERY {
+            if let Err(e) = journal.compact() {
+                error!("Failed to compact outbox, it will be compacted on restart instead: {e}");
+            }
+        }
+    }
+}
+
+impl Journal {
+    /// Rewrite the journal down to its pending requests, and carry on appending to the rewritten file
+    fn compact(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        compact(&self.path)?;
+        self.file = fs::OpenOptions::new().append(true).open(&self.path)?;
+        self.done_since_compact = 0;
+        Ok(())
+    }
+}
+
+impl Ticket {
+    /// Mark this share of the request as sent
+    pub fn done(self) {
+        if let Some(inner) = Arc::into_inner(self.0) {
+            inner.outbox.complete(inner.id);
+        }
+    }
+}
+
+impl Stored<Payload> {
+    fn into_request(self) -> Result<PlexWebhookRequest> {
+        let thumb = self
+            .thumb
+            .map(|data| BASE64.decode(data))
+            .transpose()
+            .wrap_err("Outbox entry has an invalid thumbnail")?;
+
+        Ok(PlexWebhookRequest {
+            payload: self.payload,
+            thumb,
+        })
+    }
+}
+
+/// Rewrite the journal at this path (creating it if needed) to just the entries of requests that were never marked
+/// done, returning those lines. Entries are kept as they were written, without parsing the requests in them
+fn compact(path: &Path) -> Result<Vec<String>> {
+    let mut pending: Vec<(u64, String)> = Vec::new();
+
+    if path.exists() {
+        let reader = BufReader::new(fs::File::open(path)?);
+        for (n, line) in reader.lines().enumerate() {
+            let line = line?;
+            match serde_json::from_str::<Entry<IgnoredAny>>(&line) {
+                Ok(Entry::Accepted { id, .. }) => pending.push((id, line)),
+                Ok(Entry::Done { id }) => pending.retain(|(pending_id, _)| *pending_id != id),
+                // A torn final line is expected if the process died mid-write, anything else is worth noting
+                Err(e) => warn!("Skipping unreadable outbox line {}: {e}", n + 1),
+            }
+        }
+    }
+
+    // Write the compacted journal aside and swap it in, so a crash can't lose anything
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    {
+        let mut tmp = fs::File::create(&tmp_path)?;
+        for (_, line) in &pending {
+            tmp.write_all(line.as_bytes())?;
+            tmp.write_all(b"\n")?;
+        }
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)?;
+
+    Ok(pending.into_iter().map(|(_, line)| line).collect())
+}
+
+fn write_entry<R: Serialize>(file: &mut fs::File, entry: &Entry<R>) -> Result<()> {
+    // Write each line in one go, so a crash can only ever tear the last line
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use plex_webhook::models::Event;
+
+    fn request(title: &str, thumb: Option<Vec<u8>>) -> PlexWebhookRequest {
+        let payload = serde_json::json!({
+            "event": "library.new",
+            "Metadata": { "title": title, "type": "movie" },
+        });
+        PlexWebhookRequest {
+            payload: serde_json::from_value(payload).unwrap(),
+            thumb,
+        }
+    }
+
+    fn title(request: &PlexWebhookRequest) -> &str {
+        request
+            .payload
+            .metadata
+            .as_ref()
+            .unwrap()
+            .title
+            .as_deref()
+            .unwrap()
+    }
+
+    fn line_count(path: &Path) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn resumes_unfinished_requests_after_a_crash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.jsonl");
+
+        let (outbox, pending) = Outbox::open(&path).unwrap();
+        assert!(pending.is_empty());
+
+        outbox.accept(&request("Sent", None)).unwrap().done();
+        let held = outbox
+            .accept(&request("Held", Some(vec![0xFF, 0xD8, 0x00])))
+            .unwrap();
+        let shared = outbox.accept(&request("Shared", None)).unwrap();
+        let half = shared.clone();
+        shared.done();
+
+        // The process dies with some requests unfinished, halfway through writing another
+        drop((held, half));
+        drop(outbox);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(br#"{"entry":"accepted","id":9,"req"#)
+            .unwrap();
+
+        let (outbox, pending) = Outbox::open(&path).unwrap();
+        let titles: Vec<&str> = pending.iter().map(|(_, r)| title(r)).collect();
+        assert_eq!(titles, ["Held", "Shared"]);
+        assert_eq!(pending[0].1.thumb.as_deref(), Some(&[0xFF, 0xD8, 0x00][..]));
+        assert_eq!(pending[0].1.payload.event, Event::LibraryNew);
+
+        // Compacted down to what is still pending, and new IDs don't clash with theirs
+        assert_eq!(line_count(&path), 2);
+        let next = outbox.accept(&request("Next", None)).unwrap();
+        assert!(pending.iter().all(|(ticket, _)| ticket.0.id != next.0.id));
+
+        for (ticket, _) in pending {
+            ticket.done();
+        }
+        next.done();
+        drop(outbox);
+        let (_, pending) = Outbox::open(&path).unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(line_count(&path), 0);
+    }
+
+    #[test]
+    fn thumbnails_are_journaled_as_base64() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.jsonl");
+
+        let (outbox, _) = Outbox::open(&path).unwrap();
+        let _ticket = outbox
+            .accept(&request("Poster", Some(vec![1, 2, 3])))
+            .unwrap();
+
+        let journal = fs::read_to_string(&path).unwrap();
+        assert!(journal.contains(r#""thumb":"AQID""#), "{journal}");
+    }
+
+    #[test]
+    fn compacts_while_running() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("outbox.jsonl");
+
+        let (outbox, _) = Outbox::open(&path).unwrap();
+        let held = outbox.accept(&request("Held", None)).unwrap();
+        for n in 0..COMPACT_EVERY {
+            outbox
+                .accept(&request(&format!("{n}"), None))
+                .unwrap()
+                .done();
+        }
+
+        // Only the held request is left, and the journal is still appended to after the rewrite
+        assert_eq!(line_count(&path), 1);
+        outbox.accept(&request("After", None)).unwrap().done();
+        assert_eq!(line_count(&path), 3);
+
+        held.done();
+        drop(outbox);
+        let (_, pending) = Outbox::open(&path).unwrap();
+        assert!(pending.is_empty());
+    }
+}
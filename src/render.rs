@@ -0,0 +1,1177 @@
+//! Turns plex webhook payloads into discord embeds, with a renderer for each kind of event
+
+use std::collections::BTreeMap;
+
+use discord_webhook::webhook::{
+    truncate, Attachment, Embed, EmbedField, DESCRIPTION_LIMIT, TITLE_LIMIT,
+};
+use plex_webhook::models::{Credit, Event, Metadata, Payload};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+/// Longest item summary to show, discord allows more but the rest of the embed needs room too
+const SUMMARY_LIMIT: usize = 1000;
+
+/// How many cast members to show as starring, in billing order
+const STARRING_COUNT: usize = 3;
+
+/// How many directors to show, in credit order. Anthologies can credit a long list of them
+const DIRECTOR_COUNT: usize = 2;
+
+// Embed accent colors, by kind of event
+const COLOR_LIBRARY: u32 = 0xE5A00D;
+const COLOR_PLAYBACK: u32 = 0x1F8B4C;
+const COLOR_ADMIN: u32 = 0x3498DB;
+const COLOR_ALERT: u32 = 0xE74C3C;
+
+/// Render an event into an embed based on the template, or [None] if there is nothing worth notifying about
+pub fn render(payload: &Payload, template: &Embed) -> Option<Rendered> {
+    let mut em = template.clone();
+
+    // Admin and device events in particular may not say who or where they came from
+    let account = payload.account_name().unwrap_or("Someone");
+    let server = payload.server_name().unwrap_or("the server");
+
+    match &payload.event {
+        Event::LibraryNew => {
+            let metadata = payload.metadata.as_ref()?;
+            library_new(&mut em, metadata);
+
+            let mut rendered = Rendered::from(em);
+            if matches!(metadata.media_type.as_deref(), Some("movie" | "episode")) {
+                item_details(&mut rendered, metadata);
+            }
+            links(&mut rendered, metadata);
+            return Some(rendered);
+        }
+        Event::LibraryOnDeck => {
+            em.title = Some(format!(
+                "{} is on deck for {}",
+                display_title(payload.metadata.as_ref()?),
+                account
+            ));
+            em.color = Some(COLOR_LIBRARY);
+        }
+        Event::MediaPlay | Event::PlaybackStarted => {
+            playback(&mut em, payload, account, "started", true)?
+        }
+        Event::MediaPause => playback(&mut em, payload, account, "paused", false)?,
+        Event::MediaResume => playback(&mut em, payload, account, "resumed", false)?,
+        Event::MediaStop => playback(&mut em, payload, account, "stopped", false)?,
+        Event::MediaScrobble => playback(&mut em, payload, account, "finished", true)?,
+        Event::MediaRate => {
+            em.title = Some(format!(
+                "{} rated {}",
+                account,
+                display_title(payload.metadata.as_ref()?)
+            ));
+            em.color = Some(COLOR_PLAYBACK);
+        }
+        Event::AdminDatabaseBackup => {
+            em.title = Some("Database backup finished".into());
+            em.description = Some(format!("Server: {server}"));
+            em.color = Some(COLOR_ADMIN);
+        }
+        Event::AdminDatabaseCorrupted => {
+            em.title = Some("Database corruption detected".into());
+            em.description = Some(format!(
+                "The plex database on {server} is corrupted, check the server logs"
+            ));
+            em.color = Some(COLOR_ALERT);
+        }
+        Event::DeviceNew => {
+            em.title = Some("New device connected".into());
+            let player = payload.player.as_ref();
+            let device = player
+                .and_then(|p| p.title.as_deref())
+                .unwrap_or("a new device");
+            em.description = Some(match player.and_then(|p| p.public_address.as_deref()) {
+                Some(address) => format!("{account} connected {device} from {address}"),
+                None => format!("{account} connected {device}"),
+            });
+            em.color = Some(COLOR_ADMIN);
+        }
+        Event::Unknown(name) => {
+            // Nothing to go on but the name, but if a route asked for it then send what there is
+            em.title = Some(format!("Plex event {name}"));
+            em.description = payload.metadata.as_ref().map(display_title);
+        }
+    }
+
+    Some(em.into())
+}
+
+/// An embed, along with the details of its item that routes pick from. They're only added to the embed once it's known
+/// where it goes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Rendered {
+    pub embed: Embed,
+    /// Fields showing the item's details, by the detail each shows
+    details: Vec<(Field, EmbedField)>,
+    /// Page to link the title to, which goes along with [Field::Links]
+    link: Option<String>,
+}
+
+impl From<Embed> for Rendered {
+    fn from(embed: Embed) -> Self {
+        Self {
+            embed,
+            details: Vec::new(),
+            link: None,
+        }
+    }
+}
+
+impl Rendered {
+    /// Add a detail, shown as a field named after it
+    pub fn add_detail(&mut self, field: Field, value: impl Into<String>, inline: bool) {
+        self.details
+            .push((field, EmbedField::new(field.name(), value, inline)));
+    }
+
+    /// The embed with these details added, or all of them if [None]
+    pub fn embed(&self, fields: Option<&[Field]>) -> Embed {
+        let mut em = self.embed.clone();
+        let picked: Vec<EmbedField> = self
+            .details
+            .iter()
+            .filter(|(field, _)| fields.is_none_or(|fields| fields.contains(field)))
+            .map(|(_, value)| value.clone())
+            .collect();
+        if !picked.is_empty() {
+            em.fields.get_or_insert_with(Vec::new).extend(picked);
+        }
+        if fields.is_none_or(|fields| fields.contains(&Field::Links)) {
+            em.url = self.link.clone().or(em.url);
+        }
+        em
+    }
+}
+
+/// What to send for an event, before it is dressed up for any one target
+#[derive(Debug, Clone, Default)]
+pub struct Notification {
+    pub content: Option<String>,
+    pub embeds: Vec<Rendered>,
+    /// Uploaded with the embeds, which can show them
+    pub files: Vec<Attachment>,
+}
+
+/// Key identifying the parent (and grandparent) of a newly added item, so notifications for siblings can be grouped.
+/// Episodes are grouped by show, so a batch spanning several seasons is summarized together. [None] for anything that
+/// shouldn't be grouped
+pub fn sibling_key(payload: &Payload) -> Option<String> {
+    if payload.event != Event::LibraryNew {
+        return None;
+    }
+
+    let metadata = payload.metadata.as_ref()?;
+
+    // Build a hash to uniquely ID this item's parents, if any
+    let mut hash = String::new();
+    if let Some(grandparent) = &metadata.grandparent_title {
+        hash += grandparent;
+    }
+    if let Some(parent) = metadata
+        .parent_title
+        .as_ref()
+        .filter(|_| !is_episode(metadata))
+    {
+        hash += parent;
+    }
+
+    if hash.is_empty() {
+        None
+    } else {
+        Some(hash)
+    }
+}
+
+/// Details shown as fields on new movies and episodes, which routes can pick from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Director,
+    Starring,
+    Runtime,
+    Rated,
+    AudienceScore,
+    Released,
+    Links,
+}
+
+impl Field {
+    /// Name of the embed field showing this detail
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Director => "Director",
+            Field::Starring => "Starring",
+            Field::Runtime => "Runtime",
+            Field::Rated => "Rated",
+            Field::AudienceScore => "Audience score",
+            Field::Released => "Released",
+            Field::Links => "Links",
+        }
+    }
+}
+
+/// Add the summary and every known [Field] of a movie or episode to its embed
+fn item_details(rendered: &mut Rendered, metadata: &Metadata) {
+    let em = &mut rendered.embed;
+    if let Some(summary) = metadata.summary.as_deref().filter(|s| !s.is_empty()) {
+        let summary = truncate(summary.to_string(), SUMMARY_LIMIT);
+        em.description = Some(match em.description.take() {
+            Some(description) => format!("{description}\n\n{summary}"),
+            None => summary,
+        });
+    }
+
+    let names = |credits: &Option<Vec<Credit>>, count: usize| {
+        let names: Vec<&str> = credits
+            .iter()
+            .flatten()
+            .take(count)
+            .map(|c| c.tag.as_str())
+            .collect();
+        Some(names.join(", ")).filter(|n| !n.is_empty())
+    };
+
+    let fields = [
+        (
+            Field::Director,
+            names(&metadata.director, DIRECTOR_COUNT),
+            true,
+        ),
+        (
+            Field::Starring,
+            names(&metadata.role, STARRING_COUNT),
+            false,
+        ),
+        (Field::Runtime, metadata.duration.map(running_time), true),
+        (Field::Rated, metadata.content_rating.clone(), true),
+        (
+            Field::AudienceScore,
+            metadata.audience_rating.map(|r| format!("{r:.1}/10")),
+            true,
+        ),
+        (
+            Field::Released,
+            metadata.originally_available_at.clone(),
+            true,
+        ),
+    ];
+
+    for (field, value, inline) in fields {
+        if let Some(value) = value {
+            rendered.add_detail(field, value, inline);
+        }
+    }
+}
+
+/// Link the embed's title to the item's page on IMDb, TMDB or TVDB, and list all of those it has pages on
+fn links(rendered: &mut Rendered, metadata: &Metadata) {
+    let media_type = metadata.media_type.as_deref().unwrap_or_default();
+    let links: Vec<(&str, String)> = metadata
+        .external_ids()
+        .iter()
+        .filter_map(|id| Some((id.site()?, id.url(media_type)?)))
+        .collect();
+
+    if let Some((_, url)) = links.first() {
+        rendered.link = Some(url.clone());
+
+        let list: Vec<String> = links
+            .iter()
+            .map(|(site, url)| format!("[{site}]({url})"))
+            .collect();
+        rendered.add_detail(Field::Links, list.join(" · "), false);
+    }
+}
+
+/// A newly added item held back with its siblings, along with what is needed to summarize them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sibling {
+    pub rendered: Rendered,
+    pub rating_key: Option<String>,
+    media_type: Option<String>,
+    title: Option<String>,
+    /// Season for episodes, disc for tracks
+    parent_index: Option<u64>,
+    index: Option<u64>,
+    /// Show for episodes, artist for tracks
+    grandparent_title: Option<String>,
+    /// Album for tracks
+    parent_title: Option<String>,
+    /// Length in milliseconds
+    duration: Option<u64>,
+    originally_available_at: Option<String>,
+}
+
+impl Sibling {
+    pub fn new(rendered: Rendered, metadata: &Metadata) -> Self {
+        Self {
+            rendered,
+            rating_key: metadata.rating_key.clone(),
+            media_type: metadata.media_type.clone(),
+            title: metadata.title.clone(),
+            parent_index: metadata.parent_index,
+            index: metadata.index,
+            grandparent_title: metadata.grandparent_title.clone(),
+            parent_title: metadata.parent_title.clone(),
+            duration: metadata.duration,
+            originally_available_at: metadata.originally_available_at.clone(),
+        }
+    }
+
+    fn is(&self, media_type: &str) -> bool {
+        self.media_type.as_deref() == Some(media_type)
+    }
+
+    /// "S02E05", or as much of it as is known
+    fn episode_code(&self) -> Option<String> {
+        match (self.parent_index, self.index) {
+            (Some(season), Some(index)) => Some(format!("S{season:02}E{index:02}")),
+            (None, Some(index)) => Some(format!("E{index:02}")),
+            _ => None,
+        }
+    }
+}
+
+/// Collapse a group of siblings into one embed, taking everything but the title, description and details from the
+/// first.
+///
+/// Episodes are summarized as ranges, like "S02E01–E08, E10 (9 episodes)", and tracks as an album, like
+/// "New album: Artist — Album (12 tracks, 48 min)", each followed by a list of the items if it fits. Anything else has
+/// its descriptions stacked up with newlines in between
+pub fn collapse(siblings: &[Sibling]) -> Rendered {
+    if siblings.len() == 1 {
+        return siblings[0].rendered.clone();
+    }
+
+    // Details and links of the first item would be mistaken for those of the whole group, so they are left behind
+    let mut whole_embed = siblings[0].rendered.embed.clone();
+
+    if siblings.iter().all(|s| s.is("episode")) {
+        collapse_episodes(&mut whole_embed, siblings);
+    } else if siblings.iter().all(|s| s.is("track")) {
+        collapse_tracks(&mut whole_embed, siblings);
+    } else {
+        let descriptions: Vec<&str> = siblings
+            .iter()
+            .filter_map(|s| s.rendered.embed.description.as_deref())
+            .collect();
+        whole_embed.description = Some(descriptions.join("\n")).filter(|d| !d.is_empty());
+    }
+
+    whole_embed.into()
+}
+
+fn collapse_episodes(em: &mut Embed, siblings: &[Sibling]) {
+    let mut sorted: Vec<&Sibling> = siblings.iter().collect();
+    sorted.sort_by_key(|s| (s.parent_index, s.index));
+    sorted.dedup_by_key(|s| (s.parent_index, s.index, s.title.clone()));
+
+    if let Some(show) = &sorted[0].grandparent_title {
+        em.title = Some(format!("New episodes added: {show}"));
+    }
+
+    let list: Vec<String> = sorted
+        .iter()
+        .map(|s| {
+            let title = s.title.as_deref().unwrap_or("Untitled");
+            match s.episode_code() {
+                Some(code) => format!("• {code} - {title}"),
+                None => format!("• {title}"),
+            }
+        })
+        .collect();
+
+    em.description = Some(with_list(episode_summary(&sorted), &list));
+}
+
+fn collapse_tracks(em: &mut Embed, siblings: &[Sibling]) {
+    let mut sorted: Vec<&Sibling> = siblings.iter().collect();
+    sorted.sort_by_key(|s| (s.parent_index, s.index));
+    sorted.dedup_by_key(|s| (s.parent_index, s.index, s.title.clone()));
+    let first = sorted[0];
+
+    let count = sorted.len();
+    let mut stats = format!("{count} tracks");
+    let total: u64 = sorted.iter().filter_map(|s| s.duration).sum();
+    if total > 0 {
+        stats += &format!(", {}", running_time(total));
+    }
+
+    let album = first.parent_title.as_deref().unwrap_or("Unknown album");
+    let title = match &first.grandparent_title {
+        Some(artist) => format!("New album: {artist} — {album} ({stats})"),
+        None => format!("New album: {album} ({stats})"),
+    };
+    em.title = Some(truncate(title, TITLE_LIMIT));
+
+    // Only mention discs when there is more than one
+    let discs = sorted.first().map(|s| s.parent_index) != sorted.last().map(|s| s.parent_index);
+    let mut list = Vec::new();
+    for (i, track) in sorted.iter().enumerate() {
+        if discs && (i == 0 || sorted[i - 1].parent_index != track.parent_index) {
+            if let Some(disc) = track.parent_index {
+                list.push(format!("**Disc {disc}**"));
+            }
+        }
+
+        let mut line = match track.index {
+            Some(index) => format!("{index}. "),
+            None => "• ".to_string(),
+        };
+        line += track.title.as_deref().unwrap_or("Untitled");
+        if let Some(duration) = track.duration {
+            line += &format!(" ({})", timestamp(duration));
+        }
+        list.push(line);
+    }
+
+    let released = sorted
+        .iter()
+        .find_map(|s| s.originally_available_at.as_deref())
+        .map(|date| format!("Released {date}"))
+        .unwrap_or_default();
+    // The album cover is whatever plex sent along with the tracks. Plex only gives the album's own thumb as a path on
+    // the server, which discord can't reach, so there's nothing to fall back on if it didn't
+    em.description = Some(with_list(released, &list)).filter(|d| !d.is_empty());
+}
+
+/// A summary followed by a list of items, or just the summary if the list would make it too long for an embed. The
+/// summary is cut down too if even that doesn't fit
+fn with_list(summary: String, list: &[String]) -> String {
+    let list = list.join("\n");
+    let detailed = if summary.is_empty() {
+        list
+    } else {
+        format!("{summary}\n\n{list}")
+    };
+
+    if detailed.chars().count() <= DESCRIPTION_LIMIT {
+        detailed
+    } else {
+        truncate(summary, DESCRIPTION_LIMIT)
+    }
+}
+
+/// A total length in milliseconds as "48 min" or "1 h 12 min"
+fn running_time(ms: u64) -> String {
+    let minutes = (ms + 30_000) / 60_000;
+    if minutes >= 60 {
+        format!("{} h {} min", minutes / 60, minutes % 60)
+    } else {
+        format!("{minutes} min")
+    }
+}
+
+/// Compact ranges of episode numbers by season, like "S01E09–E10, S02E01–E08, E10 (12 episodes)". Episodes are
+/// expected sorted by season and number
+fn episode_summary(episodes: &[&Sibling]) -> String {
+    let mut seasons: BTreeMap<Option<u64>, Vec<u64>> = BTreeMap::new();
+    let mut unnumbered = 0;
+    for episode in episodes {
+        match episode.index {
+            Some(index) => seasons.entry(episode.parent_index).or_default().push(index),
+            None => unnumbered += 1,
+        }
+    }
+
+    let mut parts = Vec::new();
+    let mut count = unnumbered;
+    for (season, mut indexes) in seasons {
+        indexes.dedup();
+        count += indexes.len();
+
+        // The season is only spelled out on the first range of each season
+        let mut prefix = season.map(|s| format!("S{s:02}")).unwrap_or_default();
+        for (first, last) in ranges(&indexes) {
+            parts.push(if first == last {
+                format!("{prefix}E{first:02}")
+            } else {
+                format!("{prefix}E{first:02}–E{last:02}")
+            });
+            prefix.clear();
+        }
+    }
+
+    let noun = if count == 1 { "episode" } else { "episodes" };
+    if parts.is_empty() {
+        format!("{count} {noun}")
+    } else {
+        format!("{} ({count} {noun})", parts.join(", "))
+    }
+}
+
+/// Runs of consecutive numbers in a sorted, deduplicated list, as (first, last)
+fn ranges(numbers: &[u64]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for &n in numbers {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == n => *last = n,
+            _ => ranges.push((n, n)),
+        }
+    }
+    ranges
+}
+
+/// Milliseconds as `h:mm:ss`, or `m:ss` under an hour
+pub fn timestamp(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+fn is_episode(metadata: &Metadata) -> bool {
+    metadata.media_type.as_deref() == Some("episode")
+}
+
+fn library_new(em: &mut Embed, metadata: &Metadata) {
+    // Construct a message title from media metadata
+    let mut message_title = format!(
+        "New {} added",
+        metadata.media_type.as_deref().unwrap_or("media")
+    );
+    let mut message_description = String::new();
+
+    // Gracefully fall through, and fill in context based on what kind of content this is
+    if let Some(grandparent_title) = &metadata.grandparent_title {
+        // has grandparent title, is a tv episode with associated season (parent) and show (this)
+        message_title += &format!(": {grandparent_title}");
+        if let Some(parent_title) = &metadata.parent_title {
+            // Append season context
+            message_title += &format!(" - {parent_title}");
+
+            // Construct a description from media type, title and number
+            if let Some(media_type) = &metadata.media_type {
+                message_description += &format!("{media_type} ");
+            }
+
+            // This will be the episode number for TV episodes
+            if let Some(index) = metadata.index {
+                message_description += &format!("{index}");
+            }
+
+            // Add episode title as description
+            if let Some(title) = &metadata.title {
+                message_description += &format!(": {title}");
+            }
+        }
+    } else if let Some(parent_title) = &metadata.parent_title {
+        // no grandparent title, this item refers to a season of a show, or a show without seasons?
+        message_title += &format!(": {parent_title}");
+
+        if let Some(title) = &metadata.title {
+            // Append season context
+            message_title += &format!(" - {title}");
+
+            // Construct a description from media type, title and number
+            if let Some(media_type) = &metadata.media_type {
+                message_description += &format!("{media_type} ");
+            }
+
+            // This will be the episode number for TV episodes
+            if let Some(index) = metadata.index {
+                message_description += &format!("{index}");
+            }
+        }
+    } else if let Some(title) = &metadata.title {
+        message_title += &format!(": {title}");
+    } else {
+        error!("Metadata has no title... sending empty message");
+    }
+
+    // Move into embed object
+    em.title = Some(message_title);
+    em.description = if message_description.is_empty() {
+        None
+    } else {
+        Some(message_description)
+    };
+    em.color = Some(COLOR_LIBRARY);
+}
+
+/// Playback events all read "Someone <verb> <item>", or "Someone <verb> watching/listening to <item>"
+fn playback(
+    em: &mut Embed,
+    payload: &Payload,
+    account: &str,
+    verb: &str,
+    with_activity: bool,
+) -> Option<()> {
+    let metadata = payload.metadata.as_ref()?;
+
+    let mut action = verb.to_string();
+    if with_activity {
+        action += match metadata.media_type.as_deref() {
+            Some("track") => " listening to",
+            _ => " watching",
+        };
+    }
+
+    em.title = Some(format!("{account} {action} {}", display_title(metadata)));
+    em.description = payload
+        .player
+        .as_ref()
+        .and_then(|player| player.title.as_ref())
+        .map(|player| format!("on {player}"));
+    em.color = Some(COLOR_PLAYBACK);
+
+    Some(())
+}
+
+/// A one line name for an item, with enough context to tell what it is: "Show - S01E02 - Title" for episodes,
+/// "Artist - Album - Title" for tracks and just the title for everything else
+pub fn display_title(metadata: &Metadata) -> String {
+    let title = metadata.title.as_deref().unwrap_or("something");
+
+    match (
+        metadata.media_type.as_deref(),
+        &metadata.grandparent_title,
+        &metadata.parent_title,
+    ) {
+        (Some("episode"), Some(show), _) => match (metadata.parent_index, metadata.index) {
+            (Some(season), Some(episode)) => {
+                format!("{show} - S{season:02}E{episode:02} - {title}")
+            }
+            _ => format!("{show} - {title}"),
+        },
+        (Some("track"), Some(artist), Some(album)) => format!("{artist} - {album} - {title}"),
+        _ => title.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(season: Option<u64>, index: Option<u64>) -> Sibling {
+        Sibling {
+            rendered: Rendered::default(),
+            rating_key: None,
+            media_type: Some("episode".into()),
+            title: Some(format!("Episode {}", index.unwrap_or_default())),
+            parent_index: season,
+            index,
+            grandparent_title: Some("Breaking Bad".into()),
+            parent_title: None,
+            duration: None,
+            originally_available_at: None,
+        }
+    }
+
+    fn track(disc: u64, index: u64, title: &str, duration: u64) -> Sibling {
+        Sibling {
+            rendered: Rendered::default(),
+            rating_key: None,
+            media_type: Some("track".into()),
+            title: Some(title.into()),
+            parent_index: Some(disc),
+            index: Some(index),
+            grandparent_title: Some("Radiohead".into()),
+            parent_title: Some("OK Computer".into()),
+            duration: Some(duration),
+            originally_available_at: Some("1997-05-21".into()),
+        }
+    }
+
+    fn summary(episodes: &[Sibling]) -> String {
+        let mut sorted: Vec<&Sibling> = episodes.iter().collect();
+        sorted.sort_by_key(|s| (s.parent_index, s.index));
+        episode_summary(&sorted)
+    }
+
+    /// One of the plex-webhook crate's example payloads
+    fn fixture(name: &str) -> Payload {
+        let path = format!(
+            "{}/plex-webhook/tests/fixtures/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// The title, description and color an example payload is rendered with
+    fn rendered(name: &str) -> (String, Option<String>, Option<u32>) {
+        let em = render(&fixture(name), &Embed::default()).unwrap().embed;
+        (em.title.unwrap(), em.description, em.color)
+    }
+
+    #[test]
+    fn details_are_added_as_picked() {
+        let mut rendered: Rendered = Embed::builder()
+            .title("Heat (1995)")
+            // Fields already in the embed aren't details, whatever they're called
+            .field("Runtime", "Not a detail", true)
+            .build()
+            .unwrap()
+            .into();
+        rendered.add_detail(Field::Director, "Michael Mann", true);
+        rendered.add_detail(Field::Runtime, "2 h 50 min", true);
+
+        let names = |em: Embed| -> Vec<String> {
+            em.fields
+                .unwrap_or_default()
+                .iter()
+                .map(|f| f.name().to_string())
+                .collect()
+        };
+        assert_eq!(
+            names(rendered.embed(None)),
+            ["Runtime", "Director", "Runtime"]
+        );
+        assert_eq!(
+            names(rendered.embed(Some(&[Field::Runtime, Field::Links]))),
+            ["Runtime", "Runtime"]
+        );
+        assert_eq!(names(rendered.embed(Some(&[]))), ["Runtime"]);
+    }
+
+    /// The details picked out of this metadata, with their values
+    fn details(metadata: serde_json::Value) -> (Option<String>, Vec<(Field, String)>) {
+        let metadata: Metadata = serde_json::from_value(metadata).unwrap();
+        let mut rendered = Rendered::default();
+        item_details(&mut rendered, &metadata);
+        let details = rendered
+            .details
+            .iter()
+            .map(|(field, value)| (*field, value.value().to_string()))
+            .collect();
+        (rendered.embed.description, details)
+    }
+
+    #[test]
+    fn movies_show_their_details() {
+        let (description, details) = details(serde_json::json!({
+            "type": "movie",
+            "title": "Heat",
+            "summary": "A group of professional bank robbers start to feel the heat from police.",
+            "Director": [{ "tag": "Michael Mann" }],
+            "Role": [
+                { "tag": "Al Pacino" },
+                { "tag": "Robert De Niro" },
+                { "tag": "Val Kilmer" },
+                { "tag": "Jon Voight" },
+            ],
+            "duration": 10_200_000,
+            "contentRating": "R",
+            "audienceRating": 9.25,
+            "originallyAvailableAt": "1995-12-15",
+        }));
+        assert_eq!(
+            description.as_deref(),
+            Some("A group of professional bank robbers start to feel the heat from police.")
+        );
+        assert_eq!(
+            details,
+            [
+                (Field::Director, "Michael Mann".into()),
+                (
+                    Field::Starring,
+                    "Al Pacino, Robert De Niro, Val Kilmer".into()
+                ),
+                (Field::Runtime, "2 h 50 min".into()),
+                (Field::Rated, "R".into()),
+                (Field::AudienceScore, "9.2/10".into()),
+                (Field::Released, "1995-12-15".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_credits_and_summaries_are_cut_down() {
+        let directors: Vec<_> = (1..=4)
+            .map(|n| serde_json::json!({ "tag": format!("Director {n}") }))
+            .collect();
+        let (description, details) = details(serde_json::json!({
+            "type": "movie",
+            "summary": "a".repeat(SUMMARY_LIMIT + 10),
+            "Director": directors,
+            "audienceRating": 7.0,
+        }));
+
+        let description = description.unwrap();
+        assert_eq!(description.chars().count(), SUMMARY_LIMIT);
+        assert!(description.ends_with('…'));
+        assert_eq!(
+            details,
+            [
+                (Field::Director, "Director 1, Director 2".into()),
+                (Field::AudienceScore, "7.0/10".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn summaries_go_below_the_description() {
+        let metadata: Metadata = serde_json::from_value(serde_json::json!({
+            "summary": "Walt and Jesse attempt to tie up loose ends.",
+        }))
+        .unwrap();
+        let mut rendered = Rendered::from(
+            Embed::builder()
+                .description("episode 2: Cat's in the Bag...")
+                .build()
+                .unwrap(),
+        );
+        item_details(&mut rendered, &metadata);
+        assert_eq!(
+            rendered.embed.description.as_deref(),
+            Some("episode 2: Cat's in the Bag...\n\nWalt and Jesse attempt to tie up loose ends.")
+        );
+
+        // Nothing to add, nothing added
+        let (description, details) = details(serde_json::json!({ "summary": "" }));
+        assert_eq!(description, None);
+        assert!(details.is_empty());
+    }
+
+    #[test]
+    fn title_links_go_with_the_links_detail() {
+        let mut rendered = Rendered::from(Embed::default());
+        rendered.link = Some("https://www.imdb.com/title/tt0113277".into());
+        rendered.add_detail(
+            Field::Links,
+            "[IMDb](https://www.imdb.com/title/tt0113277)",
+            false,
+        );
+
+        assert_eq!(
+            rendered.embed(None).url.as_deref(),
+            Some("https://www.imdb.com/title/tt0113277")
+        );
+        assert!(rendered.embed(Some(&[Field::Links])).url.is_some());
+        assert_eq!(rendered.embed(Some(&[Field::Runtime])).url, None);
+        assert_eq!(rendered.embed(Some(&[])).url, None);
+    }
+
+    #[test]
+    fn collapsed_groups_link_nowhere() {
+        let mut episodes = [episode(Some(1), Some(1)), episode(Some(1), Some(2))];
+        episodes[0].rendered.link = Some("https://www.imdb.com/title/tt0959621".into());
+        episodes[0]
+            .rendered
+            .add_detail(Field::Runtime, "58 min", true);
+
+        // On its own the episode keeps its link
+        assert!(collapse(&episodes[..1]).embed(None).url.is_some());
+
+        let whole = collapse(&episodes).embed(None);
+        assert_eq!(whole.url, None);
+        assert!(whole.fields.is_none());
+    }
+
+    /// The title link and Links field a new item from the example payloads is rendered with
+    fn links_of(name: &str) -> (Option<String>, Option<String>) {
+        let em = render(&fixture(name), &Embed::default())
+            .unwrap()
+            .embed(Some(&[Field::Links]));
+        let field = em
+            .fields
+            .unwrap_or_default()
+            .iter()
+            .find(|f| f.name() == "Links")
+            .map(|f| f.value().to_string());
+        (em.url, field)
+    }
+
+    #[test]
+    fn movies_link_to_every_site() {
+        assert_eq!(
+            links_of("library.new.movie"),
+            (
+                Some("https://www.imdb.com/title/tt0111161/".into()),
+                Some(
+                    "[IMDb](https://www.imdb.com/title/tt0111161/) · \
+                     [TMDB](https://www.themoviedb.org/movie/278) · \
+                     [TVDB](https://thetvdb.com/dereferrer/movie/190)"
+                        .into()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn episodes_link_to_sites_with_episode_pages() {
+        // TMDB only has pages for episodes under their show, which the ID alone isn't enough for
+        assert_eq!(
+            links_of("library.new.episode"),
+            (
+                Some("https://www.imdb.com/title/tt1054724/".into()),
+                Some(
+                    "[IMDb](https://www.imdb.com/title/tt1054724/) · \
+                     [TVDB](https://thetvdb.com/dereferrer/episode/349233)"
+                        .into()
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn collapsed_episodes_drop_their_links() {
+        let payload = fixture("library.new.episode");
+        let metadata = payload.metadata.as_ref().unwrap();
+        let sibling = || {
+            let rendered = render(&payload, &Embed::default()).unwrap();
+            Sibling::new(rendered, metadata)
+        };
+        let mut second = sibling();
+        second.index = Some(3);
+
+        let whole = collapse(&[sibling(), second]).embed(Some(&[Field::Links]));
+        assert_eq!(whole.url, None);
+        assert!(whole.fields.is_none());
+    }
+
+    #[test]
+    fn ranges_split_at_gaps() {
+        assert_eq!(ranges(&[]), []);
+        assert_eq!(ranges(&[4]), [(4, 4)]);
+        assert_eq!(ranges(&[1, 2, 3, 4]), [(1, 4)]);
+        assert_eq!(ranges(&[1, 2, 4, 6, 7]), [(1, 2), (4, 4), (6, 7)]);
+    }
+
+    #[test]
+    fn episodes_are_summarized_as_ranges() {
+        let run: Vec<Sibling> = (1..=8).map(|i| episode(Some(2), Some(i))).collect();
+        assert_eq!(summary(&run), "S02E01–E08 (8 episodes)");
+
+        let mut gapped = run.clone();
+        gapped.push(episode(Some(2), Some(10)));
+        assert_eq!(summary(&gapped), "S02E01–E08, E10 (9 episodes)");
+
+        assert_eq!(summary(&[episode(Some(1), Some(3))]), "S01E03 (1 episode)");
+    }
+
+    #[test]
+    fn each_season_is_spelled_out_once() {
+        let episodes = [
+            episode(Some(2), Some(2)),
+            episode(Some(1), Some(9)),
+            episode(Some(2), Some(1)),
+            episode(Some(1), Some(10)),
+            episode(Some(2), Some(4)),
+        ];
+        assert_eq!(
+            summary(&episodes),
+            "S01E09–E10, S02E01–E02, E04 (5 episodes)"
+        );
+    }
+
+    #[test]
+    fn episodes_sent_twice_are_counted_once() {
+        let episodes = [
+            episode(Some(1), Some(1)),
+            episode(Some(1), Some(2)),
+            episode(Some(1), Some(1)),
+        ];
+        assert_eq!(summary(&episodes), "S01E01–E02 (2 episodes)");
+
+        let whole = collapse(&episodes);
+        let description = whole.embed.description.unwrap();
+        assert_eq!(description.matches("S01E01").count(), 2, "{description}");
+        assert_eq!(description.lines().count(), 4, "{description}");
+    }
+
+    #[test]
+    fn unnumbered_episodes_are_only_counted() {
+        let episodes = [
+            episode(None, Some(5)),
+            episode(None, Some(6)),
+            episode(Some(1), None),
+        ];
+        assert_eq!(summary(&episodes), "E05–E06 (3 episodes)");
+        assert_eq!(summary(&[episode(Some(1), None)]), "1 episode");
+    }
+
+    #[test]
+    fn list_is_left_out_when_it_does_not_fit() {
+        let summary = "S01E01–E02 (2 episodes)".to_string();
+        let short = ["• S01E01 - Pilot".to_string()];
+        assert_eq!(
+            with_list(summary.clone(), &short),
+            "S01E01–E02 (2 episodes)\n\n• S01E01 - Pilot"
+        );
+        assert_eq!(with_list(String::new(), &short), "• S01E01 - Pilot");
+
+        // Right up to the limit the list still fits
+        let line = "x".repeat(DESCRIPTION_LIMIT - summary.chars().count() - 2);
+        let fits = with_list(summary.clone(), std::slice::from_ref(&line));
+        assert_eq!(fits.chars().count(), DESCRIPTION_LIMIT);
+
+        let long = [line, "y".into()];
+        assert_eq!(with_list(summary.clone(), &long), summary);
+
+        // A summary that is too long on its own is cut down
+        let huge = "z".repeat(DESCRIPTION_LIMIT + 10);
+        let cut = with_list(huge, &long);
+        assert_eq!(cut.chars().count(), DESCRIPTION_LIMIT);
+        assert!(cut.ends_with('…'));
+    }
+
+    #[test]
+    fn running_time_rounds_to_the_minute() {
+        assert_eq!(running_time(0), "0 min");
+        assert_eq!(running_time(29_999), "0 min");
+        assert_eq!(running_time(30_000), "1 min");
+        assert_eq!(running_time(48 * 60_000 + 10_000), "48 min");
+        assert_eq!(running_time(72 * 60_000), "1 h 12 min");
+        assert_eq!(running_time(120 * 60_000), "2 h 0 min");
+    }
+
+    #[test]
+    fn tracks_are_collapsed_into_an_album() {
+        let tracks = [
+            track(1, 2, "Paranoid Android", 386_000),
+            track(1, 1, "Airbag", 284_000),
+            // Sent twice, but only counted once
+            track(1, 2, "Paranoid Android", 386_000),
+        ];
+        let album = collapse(&tracks).embed;
+        assert_eq!(
+            album.title.as_deref(),
+            Some("New album: Radiohead — OK Computer (2 tracks, 11 min)")
+        );
+        assert_eq!(
+            album.description.as_deref(),
+            Some("Released 1997-05-21\n\n1. Airbag (4:44)\n2. Paranoid Android (6:26)")
+        );
+    }
+
+    #[test]
+    fn discs_are_only_mentioned_when_there_are_several() {
+        let tracks = [
+            track(2, 1, "Lift", 250_000),
+            track(1, 1, "Airbag", 284_000),
+            track(1, 2, "Paranoid Android", 386_000),
+        ];
+        let description = collapse(&tracks).embed.description.unwrap();
+        assert_eq!(
+            description,
+            "Released 1997-05-21\n\n**Disc 1**\n1. Airbag (4:44)\n2. Paranoid Android (6:26)\n**Disc 2**\n1. Lift (4:10)"
+        );
+    }
+
+    #[test]
+    fn long_album_titles_are_cut_down() {
+        let mut tracks = [
+            track(1, 1, "Airbag", 284_000),
+            track(1, 2, "Lucky", 259_000),
+        ];
+        for track in &mut tracks {
+            track.parent_title = Some("a".repeat(300));
+        }
+
+        let title = collapse(&tracks).embed.title.unwrap();
+        assert_eq!(title.chars().count(), TITLE_LIMIT);
+        assert!(title.starts_with("New album: Radiohead — aaa"));
+        assert!(title.ends_with('…'));
+    }
+    #[test]
+    fn playback_events_say_who_did_what() {
+        let on_safari = Some("on Plex Web (Safari)".to_string());
+        for (name, title) in [
+            (
+                "media.play",
+                "elan started watching The Shawshank Redemption",
+            ),
+            ("media.pause", "elan paused The Shawshank Redemption"),
+            ("media.resume", "elan resumed The Shawshank Redemption"),
+            ("media.stop", "elan stopped The Shawshank Redemption"),
+            (
+                "media.scrobble",
+                "elan finished watching The Shawshank Redemption",
+            ),
+        ] {
+            assert_eq!(
+                rendered(name),
+                (title.to_string(), on_safari.clone(), Some(COLOR_PLAYBACK)),
+                "{name}"
+            );
+        }
+
+        assert_eq!(
+            rendered("playback.started"),
+            (
+                "friend started watching The Shawshank Redemption".into(),
+                Some("on iPhone".into()),
+                Some(COLOR_PLAYBACK)
+            )
+        );
+    }
+
+    #[test]
+    fn library_events_name_the_episode() {
+        assert_eq!(
+            rendered("library.on.deck"),
+            (
+                "Breaking Bad - S01E03 - ...And the Bag's in the River is on deck for elan".into(),
+                None,
+                Some(COLOR_LIBRARY)
+            )
+        );
+        assert_eq!(
+            rendered("media.rate"),
+            (
+                "elan rated Breaking Bad - S01E02 - Cat's in the Bag...".into(),
+                None,
+                Some(COLOR_PLAYBACK)
+            )
+        );
+    }
+
+    #[test]
+    fn admin_events_say_which_server() {
+        assert_eq!(
+            rendered("admin.database.backup"),
+            (
+                "Database backup finished".into(),
+                Some("Server: Office".into()),
+                Some(COLOR_ADMIN)
+            )
+        );
+        assert_eq!(
+            rendered("admin.database.corrupted"),
+            (
+                "Database corruption detected".into(),
+                Some("The plex database on Office is corrupted, check the server logs".into()),
+                Some(COLOR_ALERT)
+            )
+        );
+    }
+
+    #[test]
+    fn new_devices_say_where_they_connected_from() {
+        assert_eq!(
+            rendered("device.new"),
+            (
+                "New device connected".into(),
+                Some("elan connected New Roku from 203.0.113.7".into()),
+                Some(COLOR_ADMIN)
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_events_are_sent_by_name() {
+        let mut payload = fixture("media.play");
+        payload.event = Event::Unknown("media.skip".into());
+        let em = render(&payload, &Embed::default()).unwrap().embed;
+        assert_eq!(em.title.as_deref(), Some("Plex event media.skip"));
+        assert_eq!(em.description.as_deref(), Some("The Shawshank Redemption"));
+        assert_eq!(em.color, None);
+    }
+
+    #[test]
+    fn events_without_their_item_are_not_sent() {
+        let mut payload = fixture("media.play");
+        payload.metadata = None;
+        assert!(render(&payload, &Embed::default()).is_none());
+    }
+}
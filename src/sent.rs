@@ -0,0 +1,234 @@
+//! Remembers the messages posted for recently added items, so they can be edited or deleted if plex sends the item
+//! again, even across restarts.
+//!
+//! The store is a single JSON file listing the messages posted for each item, or group of items sent together, with the
+//! items' rating keys. Messages are kept by webhook ID rather than URL, so the webhook's token is never written to disk.
+//! The store is small, so it is simply rewritten whenever messages are remembered or forgotten.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::{eyre::WrapErr, Result};
+use discord_webhook::webhook::{thread_url, webhook_id};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::config::Target;
+use crate::render::Sibling;
+
+/// How many items to remember messages for, the oldest are forgotten first
+const REMEMBERED_ITEMS: usize = 1000;
+
+/// A message that was posted to a target, which can be edited later. The webhook is only known by ID, the URL to
+/// reach the message through comes from the target it was posted to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Posted {
+    pub webhook_id: String,
+    /// Thread, or forum post, the message went in
+    pub thread_id: Option<String>,
+    pub message_id: String,
+}
+
+impl Posted {
+    /// The target, out of these, that this message was posted to
+    pub fn target<'a>(&self, targets: &'a [Target]) -> Option<&'a Target> {
+        targets
+            .iter()
+            .find(|target| webhook_id(&target.url) == self.webhook_id)
+    }
+
+    /// URL to reach this message through, given the URL of the webhook it was posted through
+    pub fn url(&self, webhook_url: &str) -> String {
+        match &self.thread_id {
+            Some(id) => thread_url(webhook_url, id),
+            None => webhook_url.to_string(),
+        }
+    }
+}
+
+/// Messages posted for a group of newly added items, which is often a single one
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Record {
+    /// Rating keys of the items the messages show
+    pub items: Vec<String>,
+    pub posted: Vec<Posted>,
+    /// The items of a group, kept so its messages can be redrawn without some of them
+    #[serde(default)]
+    pub siblings: Vec<Sibling>,
+}
+
+impl Record {
+    /// Whether this shows exactly these items, no more and no fewer
+    pub fn shows(&self, items: &[String]) -> bool {
+        self.items.len() == items.len() && self.items.iter().all(|item| items.contains(item))
+    }
+}
+
+/// Handle to the store of posted messages, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Sent {
+    path: PathBuf,
+    /// Oldest first
+    records: Arc<Mutex<VecDeque<Record>>>,
+    capacity: usize,
+}
+
+impl Sent {
+    /// Load the store at this path, which is created the first time messages are remembered
+    pub fn open(path: &Path) -> Result<Self> {
+        let records = if path.exists() {
+            let text = fs::read(path).wrap_err_with(|| {
+                format!("Failed to read sent message store {}", path.display())
+            })?;
+            serde_json::from_slice(&text).wrap_err_with(|| {
+                format!("Failed to parse sent message store {}", path.display())
+            })?
+        } else {
+            VecDeque::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            records: Arc::new(Mutex::new(records)),
+            capacity: REMEMBERED_ITEMS,
+        })
+    }
+
+    /// Forget the messages showing this item, returning them. The other items they show are forgotten along with them
+    pub fn take(&self, item: &str) -> Option<Record> {
+        let mut records = self.records.lock().unwrap();
+        let position = records
+            .iter()
+            .position(|record| record.items.iter().any(|i| i == item))?;
+        let record = records.remove(position);
+
+        self.save_logged(&records);
+        record
+    }
+
+    /// Remember the messages posted for some items, in place of any remembered for them before
+    pub fn remember(&self, record: Record) {
+        if record.items.is_empty() || record.posted.is_empty() {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        records.retain(|r| !r.items.iter().any(|item| record.items.contains(item)));
+        records.push_back(record);
+
+        // Items are counted rather than records, since a record for a whole season outweighs one for a movie
+        let mut count: usize = records.iter().map(|r| r.items.len()).sum();
+        while count > self.capacity && records.len() > 1 {
+            if let Some(oldest) = records.pop_front() {
+                count -= oldest.items.len();
+            }
+        }
+
+        self.save_logged(&records);
+    }
+
+    fn save_logged(&self, records: &VecDeque<Record>) {
+        if let Err(e) = self.save(records) {
+            error!("Failed to save sent message store, changes will be lost on restart: {e}");
+        }
+    }
+
+    fn save(&self, records: &VecDeque<Record>) -> Result<()> {
+        // Write the whole store aside and swap it in, so a crash can't leave it half written
+        let tmp_path = PathBuf::from(format!("{}.tmp", self.path.display()));
+        fs::write(&tmp_path, serde_json::to_vec(records)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(items: &[&str], message_id: &str) -> Record {
+        Record {
+            items: items.iter().map(|item| item.to_string()).collect(),
+            posted: vec![Posted {
+                webhook_id: "1234".into(),
+                thread_id: Some("42".into()),
+                message_id: message_id.into(),
+            }],
+            siblings: Vec::new(),
+        }
+    }
+
+    fn message(record: Option<Record>) -> Option<String> {
+        record.map(|r| r.posted[0].message_id.clone())
+    }
+
+    #[test]
+    fn remembers_messages_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sent.json");
+
+        let sent = Sent::open(&path).unwrap();
+        assert!(sent.take("1936").is_none());
+        sent.remember(record(&["1936"], "7"));
+        sent.remember(record(&["2051"], "8"));
+        // Nothing to remember for items that weren't posted anywhere
+        sent.remember(Record {
+            posted: Vec::new(),
+            ..record(&["3102"], "9")
+        });
+
+        let sent = Sent::open(&path).unwrap();
+        assert_eq!(message(sent.take("1936")).as_deref(), Some("7"));
+        assert!(sent.take("3102").is_none());
+
+        // Taking an item forgets it, on disk too
+        let sent = Sent::open(&path).unwrap();
+        assert!(sent.take("1936").is_none());
+        assert_eq!(message(sent.take("2051")).as_deref(), Some("8"));
+    }
+
+    #[test]
+    fn groups_are_forgotten_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let sent = Sent::open(&dir.path().join("sent.json")).unwrap();
+        sent.remember(record(&["e1", "e2", "e3"], "7"));
+
+        let group = sent.take("e2").unwrap();
+        assert!(group.shows(&["e3".into(), "e1".into(), "e2".into()]));
+        assert!(!group.shows(&["e2".into()]));
+        assert!(sent.take("e1").is_none());
+        assert!(sent.take("e3").is_none());
+    }
+
+    #[test]
+    fn newer_messages_replace_older_ones_for_the_same_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let sent = Sent::open(&dir.path().join("sent.json")).unwrap();
+        sent.remember(record(&["e1", "e2"], "7"));
+        sent.remember(record(&["e2", "e3"], "8"));
+
+        assert!(sent.take("e1").is_none());
+        assert_eq!(message(sent.take("e2")).as_deref(), Some("8"));
+    }
+
+    #[test]
+    fn forgets_the_oldest_items() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sent.json");
+
+        let sent = Sent {
+            capacity: 3,
+            ..Sent::open(&path).unwrap()
+        };
+        sent.remember(record(&["a"], "1"));
+        sent.remember(record(&["b"], "2"));
+        sent.remember(record(&["c", "d"], "3"));
+
+        let sent = Sent::open(&path).unwrap();
+        assert!(sent.take("a").is_none());
+        assert_eq!(message(sent.take("b")).as_deref(), Some("2"));
+        assert_eq!(message(sent.take("d")).as_deref(), Some("3"));
+    }
+}
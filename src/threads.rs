@@ -0,0 +1,114 @@
+//! Remembers which forum post was created for each show, so every episode of a series lands in the same post, even
+//! across restarts.
+//!
+//! The store is a single JSON file mapping each webhook's ID to the thread IDs of the posts made through it, keyed by
+//! the show's rating key. Webhooks are known by ID rather than URL, so the token in the URL is never written to disk.
+//! The store is small, so it is simply rewritten whenever a new post is remembered.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::{eyre::WrapErr, Result};
+use discord_webhook::webhook::webhook_id;
+use tracing::error;
+
+/// Webhook ID to show key to thread ID
+type ThreadIds = HashMap<String, HashMap<String, String>>;
+
+/// Handle to the thread store, cheap to clone
+#[derive(Debug, Clone)]
+pub struct Threads {
+    path: PathBuf,
+    ids: Arc<Mutex<ThreadIds>>,
+}
+
+impl Threads {
+    /// Load the store at this path, which is created the first time a thread is remembered
+    pub fn open(path: &Path) -> Result<Self> {
+        let ids: ThreadIds = if path.exists() {
+            let text = fs::read(path)
+                .wrap_err_with(|| format!("Failed to read thread store {}", path.display()))?;
+            serde_json::from_slice(&text)
+                .wrap_err_with(|| format!("Failed to parse thread store {}", path.display()))?
+        } else {
+            HashMap::new()
+        };
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            ids: Arc::new(Mutex::new(ids)),
+        })
+    }
+
+    /// Thread previously created through this webhook for this key, if any
+    pub fn get(&self, url: &str, key: &str) -> Option<String> {
+        self.ids
+            .lock()
+            .unwrap()
+            .get(webhook_id(url))?
+            .get(key)
+            .cloned()
+    }
+
+    /// Remember the thread created through this webhook for this key
+    pub fn remember(&self, url: &str, key: &str, thread_id: &str) {
+        let mut ids = self.ids.lock().unwrap();
+        ids.entry(webhook_id(url).to_string())
+            .or_default()
+            .insert(key.to_string(), thread_id.to_string());
+
+        if let Err(e) = self.save(&ids) {
+            error!(
+                "Failed to save thread store, thread {thread_id} will be forgotten on restart: {e}"
+            );
+        }
+    }
+
+    fn save(&self, ids: &ThreadIds) -> Result<()> {
+        // Write the whole store aside and swap it in, so a crash can't leave it half written
+        let tmp_path = PathBuf::from(format!("{}.tmp", self.path.display()));
+        fs::write(&tmp_path, serde_json::to_vec_pretty(ids)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://discord.com/api/webhooks/1234/s3cr3t-t0k3n";
+
+    #[test]
+    fn remembers_threads_across_restarts_without_tokens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("threads.json");
+
+        let threads = Threads::open(&path).unwrap();
+        assert_eq!(threads.get(URL, "show"), None);
+        assert!(!path.exists());
+
+        threads.remember(URL, "show", "42");
+        assert_eq!(threads.get(URL, "show").as_deref(), Some("42"));
+        // The same webhook through a different URL, like a new token, still finds its posts
+        assert_eq!(
+            threads
+                .get("https://discord.com/api/webhooks/1234/new", "show")
+                .as_deref(),
+            Some("42")
+        );
+        assert_eq!(
+            threads.get("https://discord.com/api/webhooks/5678/s3cr3t-t0k3n", "show"),
+            None
+        );
+        assert_eq!(threads.get(URL, "other"), None);
+
+        let store = fs::read_to_string(&path).unwrap();
+        assert!(!store.contains("s3cr3t"), "{store}");
+
+        let threads = Threads::open(&path).unwrap();
+        assert_eq!(threads.get(URL, "show").as_deref(), Some("42"));
+    }
+}
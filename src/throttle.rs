@@ -0,0 +1,219 @@
+//! Holds back notifications for siblings (episodes of the same season, tracks of the same album) so they can be sent
+//! together.
+//!
+//! Items are grouped by key, and a group is released once nothing new has joined it for the whole window, or once the
+//! max delay has passed since its first item, whichever comes first. Time comes from tokio's clock, so it can be paused
+//! and advanced in tests.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Group<T> {
+    /// When the first item joined
+    started: Instant,
+    /// When the latest item joined
+    updated: Instant,
+    items: Vec<T>,
+}
+
+/// Groups of items waiting for their window to pass
+#[derive(Debug)]
+pub struct Throttle<K, T> {
+    window: Duration,
+    max_delay: Option<Duration>,
+    groups: HashMap<K, Group<T>>,
+}
+
+impl<K: Hash + Eq, T> Throttle<K, T> {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            max_delay: None,
+            groups: HashMap::new(),
+        }
+    }
+
+    /// Release groups this long after their first item at the latest, even if items keep joining them
+    pub fn with_max_delay(mut self, max_delay: Option<Duration>) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Add an item to its group, holding the whole group back for another window (up to the max delay)
+    pub fn push(&mut self, key: K, item: T) {
+        let now = Instant::now();
+        let group = self.groups.entry(key).or_insert_with(|| Group {
+            started: now,
+            updated: now,
+            items: Vec::new(),
+        });
+        group.updated = now;
+        group.items.push(item);
+    }
+
+    /// Items held in this key's group so far, in the order they came in, or [None] if there's no such group
+    pub fn items(&self, key: &K) -> Option<&[T]> {
+        self.groups.get(key).map(|group| group.items.as_slice())
+    }
+
+    /// When the next group is due to be released, or [None] if nothing is held
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.groups.values().map(|group| self.deadline(group)).min()
+    }
+
+    /// Remove and return every group whose window has passed, with its items in the order they came in
+    pub fn take_ready(&mut self) -> Vec<(K, Vec<T>)> {
+        let now = Instant::now();
+
+        let (ready, held): (Vec<_>, Vec<_>) = std::mem::take(&mut self.groups)
+            .into_iter()
+            .partition(|(_, group)| self.deadline(group) <= now);
+        self.groups = held.into_iter().collect();
+
+        ready
+            .into_iter()
+            .map(|(key, group)| (key, group.items))
+            .collect()
+    }
+
+    fn deadline(&self, group: &Group<T>) -> Instant {
+        let deadline = group.updated + self.window;
+        match self.max_delay {
+            Some(max_delay) => deadline.min(group.started + max_delay),
+            None => deadline,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(30);
+
+    fn sorted(mut ready: Vec<(&'static str, Vec<u32>)>) -> Vec<(&'static str, Vec<u32>)> {
+        ready.sort();
+        ready
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn groups_by_key() {
+        let mut throttle = Throttle::new(WINDOW);
+        throttle.push("a", 1);
+        throttle.push("b", 2);
+        throttle.push("a", 3);
+
+        tokio::time::advance(WINDOW).await;
+        assert_eq!(
+            sorted(throttle.take_ready()),
+            vec![("a", vec![1, 3]), ("b", vec![2])]
+        );
+        assert_eq!(throttle.next_deadline(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn holds_until_window_passes() {
+        let mut throttle = Throttle::new(WINDOW);
+        throttle.push("a", 1);
+
+        tokio::time::advance(WINDOW - Duration::from_secs(1)).await;
+        assert!(throttle.take_ready().is_empty());
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![1])]);
+        assert!(throttle.take_ready().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn new_items_extend_the_window() {
+        let mut throttle = Throttle::new(WINDOW);
+        throttle.push("a", 1);
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        throttle.push("a", 2);
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert!(throttle.take_ready().is_empty());
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![1, 2])]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn next_deadline_is_the_earliest_group() {
+        let mut throttle = Throttle::new(WINDOW);
+        assert_eq!(throttle.next_deadline(), None);
+
+        let start = Instant::now();
+        throttle.push("a", 1);
+        tokio::time::advance(Duration::from_secs(10)).await;
+        throttle.push("b", 2);
+        assert_eq!(throttle.next_deadline(), Some(start + WINDOW));
+
+        // Once the earlier group is released, the deadline moves on to the one still held rather than staying put
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![1])]);
+        assert_eq!(
+            throttle.next_deadline(),
+            Some(start + Duration::from_secs(10) + WINDOW)
+        );
+
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(throttle.take_ready(), vec![("b", vec![2])]);
+        assert_eq!(throttle.next_deadline(), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn max_delay_caps_the_hold() {
+        let mut throttle = Throttle::new(WINDOW).with_max_delay(Some(Duration::from_secs(50)));
+        let start = Instant::now();
+
+        // A steady trickle would otherwise hold the group back forever
+        for item in 1..=3 {
+            throttle.push("a", item);
+            tokio::time::advance(Duration::from_secs(15)).await;
+            assert!(throttle.take_ready().is_empty());
+        }
+        assert_eq!(
+            throttle.next_deadline(),
+            Some(start + Duration::from_secs(50))
+        );
+
+        throttle.push("a", 4);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![1, 2, 3, 4])]);
+
+        // Later items start a fresh group, with a fresh window
+        throttle.push("a", 5);
+        assert_eq!(throttle.next_deadline(), Some(Instant::now() + WINDOW));
+        tokio::time::advance(WINDOW).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![5])]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn held_items_can_be_looked_at() {
+        let mut throttle = Throttle::new(WINDOW);
+        assert_eq!(throttle.items(&"a"), None);
+
+        throttle.push("a", 1);
+        throttle.push("a", 2);
+        assert_eq!(throttle.items(&"a"), Some(&[1, 2][..]));
+
+        tokio::time::advance(WINDOW).await;
+        throttle.take_ready();
+        assert_eq!(throttle.items(&"a"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sleeping_until_the_deadline_releases_the_group() {
+        let mut throttle = Throttle::new(WINDOW);
+        throttle.push("a", 1);
+
+        tokio::time::sleep_until(throttle.next_deadline().unwrap()).await;
+        assert_eq!(throttle.take_ready(), vec![("a", vec![1])]);
+    }
+}